@@ -12,7 +12,7 @@ cat /etc/*release*
 uname -a
 rustc --version
 rustup target add wasm32-unknown-unknown
-cargo build --target wasm32-unknown-unknown --release
+cargo build --target wasm32-unknown-unknown --release --bin caticorn
 rm -rf build
 cargo install wasm-bindgen-cli --version 0.2.87
 wasm-bindgen --out-dir ./build/ --target web ./target/wasm32-unknown-unknown/release/caticorn.wasm
@@ -0,0 +1,308 @@
+//! Relay server for online rounds. Pairs up clients as they join, collects
+//! their input and sends both of them the same stream of tick frames, so
+//! that their simulations stay in lockstep. Candy spawns and eats are
+//! decided here.
+//!
+//!     cargo run --bin relay -- 127.0.0.1:9001
+//!     cargo run -- --connect ws://127.0.0.1:9001
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::ErrorKind;
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc::{channel, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use clap::Parser;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use tungstenite::Message;
+
+#[path = "../protocol.rs"]
+mod protocol;
+
+use protocol::{
+    pick_weighted, CandyEaten, CandySpawn, ClientMessage, PlayerInput, ServerMessage, TickFrame,
+    ARENA_HEIGHT, ARENA_WIDTH, CANDY_KIND_WEIGHTS, CANDY_SPAWN_INTERVAL_TICKS, INITIAL_CANDIES,
+    MAX_CANDY, PLAYERS,
+};
+
+/// How long a client thread waits for a message before checking whether it
+/// has anything to send
+const POLL_INTERVAL: Duration = Duration::from_millis(2);
+
+#[derive(Parser, Debug)]
+#[command(about = "Relay server for online caticorn rounds")]
+struct Cli {
+    /// Address to listen on
+    #[arg(default_value = "127.0.0.1:9001")]
+    address: String,
+}
+
+type ClientId = u64;
+type MatchId = u64;
+
+struct Match {
+    clients: [ClientId; PLAYERS],
+    inputs: BTreeMap<u32, [Option<PlayerInput>; PLAYERS]>,
+    next_tick: u32,
+    live_candies: HashSet<u32>,
+    next_candy_id: u32,
+    eaten: Vec<CandyEaten>,
+    rng: StdRng,
+}
+
+impl Match {
+    fn new(clients: [ClientId; PLAYERS]) -> Self {
+        Match {
+            clients,
+            inputs: BTreeMap::new(),
+            next_tick: 0,
+            live_candies: HashSet::new(),
+            next_candy_id: 0,
+            eaten: Vec::new(),
+            rng: StdRng::from_entropy(),
+        }
+    }
+
+    fn spawn_candy(&mut self) -> CandySpawn {
+        let id = self.next_candy_id;
+        self.next_candy_id += 1;
+        self.live_candies.insert(id);
+
+        let kind = pick_weighted(CANDY_KIND_WEIGHTS, &mut self.rng).unwrap_or(0);
+
+        let direction_x = self.rng.gen::<f32>() * 2.0 - 1.0;
+        let direction_y = self.rng.gen::<f32>() * 2.0 - 1.0;
+        let length = (direction_x * direction_x + direction_y * direction_y)
+            .sqrt()
+            .max(f32::EPSILON);
+        CandySpawn {
+            id,
+            kind: kind as u8,
+            x: self.rng.gen::<f32>() * ARENA_WIDTH - ARENA_WIDTH / 2.0,
+            y: self.rng.gen::<f32>() * ARENA_HEIGHT - ARENA_HEIGHT / 2.0,
+            direction_x: direction_x / length,
+            direction_y: direction_y / length,
+        }
+    }
+
+    /// Frames for every tick that all players have sent their input for
+    fn ready_frames(&mut self) -> Vec<TickFrame> {
+        let mut frames = Vec::new();
+        while let Some(inputs) = self.complete_inputs(self.next_tick) {
+            let tick = self.next_tick;
+            self.inputs.remove(&tick);
+
+            let mut spawns = Vec::new();
+            if tick == 0 {
+                for _ in 0..INITIAL_CANDIES {
+                    spawns.push(self.spawn_candy());
+                }
+            } else if tick % CANDY_SPAWN_INTERVAL_TICKS == 0 && self.live_candies.len() <= MAX_CANDY
+            {
+                spawns.push(self.spawn_candy());
+            }
+
+            frames.push(TickFrame {
+                tick,
+                inputs,
+                spawns,
+                eaten: std::mem::take(&mut self.eaten),
+            });
+            self.next_tick += 1;
+        }
+        frames
+    }
+
+    fn complete_inputs(&self, tick: u32) -> Option<[PlayerInput; PLAYERS]> {
+        let inputs = self.inputs.get(&tick)?;
+        let mut complete = [PlayerInput::default(); PLAYERS];
+        for (input, received) in complete.iter_mut().zip(inputs) {
+            *input = (*received)?;
+        }
+        Some(complete)
+    }
+
+    /// The first caticorn to claim a candy gets it
+    fn eat(&mut self, candy: u32, player: u8) {
+        if self.live_candies.remove(&candy) {
+            self.eaten.push(CandyEaten { id: candy, player });
+        }
+    }
+}
+
+#[derive(Default)]
+struct Lobby {
+    senders: HashMap<ClientId, Sender<ServerMessage>>,
+    waiting: Vec<ClientId>,
+    matches: HashMap<MatchId, Match>,
+    /// Which match each playing client is in, and as which player
+    players: HashMap<ClientId, (MatchId, u8)>,
+    next_match_id: MatchId,
+}
+
+impl Lobby {
+    fn send(&self, client: ClientId, message: ServerMessage) {
+        if let Some(sender) = self.senders.get(&client) {
+            // A client that is gone cleans up after itself
+            let _ = sender.send(message);
+        }
+    }
+
+    fn handle(&mut self, client: ClientId, message: ClientMessage) {
+        match message {
+            ClientMessage::Join => {
+                self.leave_match(client);
+                if !self.waiting.contains(&client) {
+                    self.waiting.push(client);
+                }
+                if self.waiting.len() >= PLAYERS {
+                    let clients: [ClientId; PLAYERS] = self
+                        .waiting
+                        .drain(..PLAYERS)
+                        .collect::<Vec<_>>()
+                        .try_into()
+                        .unwrap();
+                    self.start_match(clients);
+                }
+            }
+            ClientMessage::Leave => {
+                self.waiting.retain(|&waiting| waiting != client);
+                self.leave_match(client);
+            }
+            ClientMessage::Input { tick, input } => {
+                let Some(&(match_id, player)) = self.players.get(&client) else {
+                    return;
+                };
+                let Some(game) = self.matches.get_mut(&match_id) else {
+                    return;
+                };
+                if tick < game.next_tick {
+                    return;
+                }
+                game.inputs.entry(tick).or_default()[player as usize] = Some(input);
+                let clients = game.clients;
+                for frame in game.ready_frames() {
+                    for client in clients {
+                        self.send(client, ServerMessage::Tick(frame.clone()));
+                    }
+                }
+            }
+            ClientMessage::Eat { candy } => {
+                let Some(&(match_id, player)) = self.players.get(&client) else {
+                    return;
+                };
+                if let Some(game) = self.matches.get_mut(&match_id) {
+                    game.eat(candy, player);
+                }
+            }
+        }
+    }
+
+    fn start_match(&mut self, clients: [ClientId; PLAYERS]) {
+        let match_id = self.next_match_id;
+        self.next_match_id += 1;
+        println!("match {match_id}: clients {clients:?}");
+
+        for (player, &client) in clients.iter().enumerate() {
+            self.players.insert(client, (match_id, player as u8));
+            self.send(
+                client,
+                ServerMessage::Start {
+                    player: player as u8,
+                },
+            );
+        }
+        self.matches.insert(match_id, Match::new(clients));
+    }
+
+    /// Ends the client's match, if it is in one, and tells the opponent
+    fn leave_match(&mut self, client: ClientId) {
+        let Some((match_id, _)) = self.players.remove(&client) else {
+            return;
+        };
+        let Some(game) = self.matches.remove(&match_id) else {
+            return;
+        };
+        println!("match {match_id}: over after {} ticks", game.next_tick);
+        for other in game.clients {
+            if other != client && self.players.remove(&other).is_some() {
+                self.send(other, ServerMessage::OpponentLeft);
+            }
+        }
+    }
+
+    fn disconnect(&mut self, client: ClientId) {
+        self.senders.remove(&client);
+        self.waiting.retain(|&waiting| waiting != client);
+        self.leave_match(client);
+    }
+}
+
+fn serve(stream: TcpStream, client: ClientId, lobby: &Mutex<Lobby>) -> Result<(), String> {
+    let mut socket = tungstenite::accept(stream).map_err(|e| e.to_string())?;
+    socket
+        .get_ref()
+        .set_read_timeout(Some(POLL_INTERVAL))
+        .map_err(|e| e.to_string())?;
+
+    let (sender, outgoing) = channel();
+    lobby.lock().unwrap().senders.insert(client, sender);
+
+    loop {
+        match socket.read() {
+            Ok(Message::Text(text)) => match protocol::decode(&text) {
+                Ok(message) => lobby.lock().unwrap().handle(client, message),
+                Err(e) => eprintln!("client {client}: ignoring bad message: {e}"),
+            },
+            Ok(Message::Close(_)) => return Ok(()),
+            Ok(_) => {}
+            Err(tungstenite::Error::Io(e))
+                if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {}
+            Err(tungstenite::Error::ConnectionClosed) => return Ok(()),
+            Err(e) => return Err(e.to_string()),
+        }
+
+        while let Ok(message) = outgoing.try_recv() {
+            socket
+                .send(Message::Text(protocol::encode(&message)))
+                .map_err(|e| e.to_string())?;
+        }
+    }
+}
+
+fn main() {
+    let args = Cli::parse();
+
+    let listener = match TcpListener::bind(&args.address) {
+        Ok(listener) => listener,
+        Err(e) => {
+            eprintln!("failed to listen on {}: {e}", args.address);
+            std::process::exit(1);
+        }
+    };
+    println!("relay listening on ws://{}", args.address);
+
+    let lobby = Arc::new(Mutex::new(Lobby::default()));
+
+    for (client, stream) in (0..).zip(listener.incoming()) {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                eprintln!("failed to accept connection: {e}");
+                continue;
+            }
+        };
+        let lobby = lobby.clone();
+        thread::spawn(move || {
+            println!("client {client}: connected");
+            if let Err(e) = serve(stream, client, &lobby) {
+                eprintln!("client {client}: {e}");
+            }
+            lobby.lock().unwrap().disconnect(client);
+            println!("client {client}: disconnected");
+        });
+    }
+}
@@ -0,0 +1,116 @@
+//! The kinds of candy and what sets them apart
+
+use std::collections::BTreeMap;
+
+use bevy::prelude::*;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::{protocol, POINTS_PER_CANDY};
+
+/// How long a licorice goes one way before veering the other
+pub const ZIGZAG_LEG_SECONDS: f32 = 0.4;
+/// How far off its heading a licorice veers, in radians
+pub const ZIGZAG_ANGLE: f32 = 0.6;
+/// Angle between the heading of a gumdrop and each of its halves, in radians
+pub const SPLIT_ANGLE: f32 = 0.5;
+
+#[derive(
+    Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default, Debug,
+)]
+pub enum CandyKind {
+    #[default]
+    Donut,
+    Sprinkles,
+    Licorice,
+    Gumdrop,
+    Rotten,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum CandyBehavior {
+    /// Bounces around, pushed away by caticorns
+    Plain,
+    /// Runs from caticorns, from further away and harder
+    Flee,
+    /// Veers left and right of its heading
+    Zigzag,
+    /// Breaks into two donuts when it hits a wall
+    Split,
+}
+
+/// Every kind is drawn with the donut sprite, told apart by its tint and
+/// its size
+pub struct CandyProperties {
+    /// Tint of the sprite
+    pub color: Color,
+    /// Scale of the sprite and of the shape caticorns and walls meet
+    pub size: f32,
+    /// Multiplies the level's candy speed
+    pub speed: f32,
+    /// Points before the combo multiplier. Candy worth nothing breaks the
+    /// combo and doesn't count as eaten.
+    pub points: u32,
+    pub behavior: CandyBehavior,
+}
+
+impl CandyKind {
+    pub const ALL: [CandyKind; 5] = [
+        CandyKind::Donut,
+        CandyKind::Sprinkles,
+        CandyKind::Licorice,
+        CandyKind::Gumdrop,
+        CandyKind::Rotten,
+    ];
+
+    pub fn properties(self) -> CandyProperties {
+        match self {
+            CandyKind::Donut => CandyProperties {
+                color: Color::WHITE,
+                size: 1.0,
+                speed: 1.0,
+                points: POINTS_PER_CANDY,
+                behavior: CandyBehavior::Plain,
+            },
+            CandyKind::Sprinkles => CandyProperties {
+                color: Color::rgb(1.0, 0.6, 0.9),
+                size: 0.7,
+                speed: 1.3,
+                points: 25,
+                behavior: CandyBehavior::Flee,
+            },
+            CandyKind::Licorice => CandyProperties {
+                color: Color::rgb(1.0, 0.35, 0.35),
+                size: 0.85,
+                speed: 1.1,
+                points: 15,
+                behavior: CandyBehavior::Zigzag,
+            },
+            CandyKind::Gumdrop => CandyProperties {
+                color: Color::rgb(0.5, 1.0, 0.9),
+                size: 1.35,
+                speed: 0.8,
+                points: 15,
+                behavior: CandyBehavior::Split,
+            },
+            CandyKind::Rotten => CandyProperties {
+                color: Color::rgb(0.45, 0.6, 0.25),
+                size: 1.15,
+                speed: 0.7,
+                points: 0,
+                behavior: CandyBehavior::Plain,
+            },
+        }
+    }
+
+    pub fn from_index(index: u8) -> Option<CandyKind> {
+        CandyKind::ALL.get(index as usize).copied()
+    }
+
+    /// Picks a kind of candy, each with a chance in proportion to its weight
+    pub fn choose(weights: &BTreeMap<CandyKind, u32>, rng: &mut impl Rng) -> CandyKind {
+        protocol::pick_weighted(weights.values().copied(), rng)
+            .and_then(|index| weights.keys().nth(index).copied())
+            .unwrap_or(CandyKind::Donut)
+    }
+}
@@ -0,0 +1,421 @@
+//! What the caticorns, candy and pickups are shaped like when they touch, and
+//! a grid of where all the candy is that narrows down what might be touching.
+//!
+//! The shapes are capsules fitted by hand to the opaque pixels of the sprites,
+//! rather than worked out from the images at runtime: headless runs, replays
+//! and online rounds never load the images and still have to agree on every
+//! candy eaten.
+
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+
+use crate::Candy;
+
+/// The points within `radius` of the segment from `a` to `b`, in unscaled
+/// sprite pixels from the center of the sprite, y up. A circle is a capsule
+/// with `a` and `b` in the same place.
+#[derive(Clone, Copy, Debug)]
+pub struct Capsule {
+    pub a: Vec2,
+    pub b: Vec2,
+    pub radius: f32,
+}
+
+impl Capsule {
+    pub const fn new(a: Vec2, b: Vec2, radius: f32) -> Self {
+        Capsule { a, b, radius }
+    }
+
+    pub const fn circle(center: Vec2, radius: f32) -> Self {
+        Capsule {
+            a: center,
+            b: center,
+            radius,
+        }
+    }
+
+    /// The capsule where the entity is. Shapes are scaled by the larger of
+    /// the x and y scale, which are the same for everything in the arena.
+    fn placed(&self, transform: &Transform) -> Capsule {
+        let scale = transform.scale.x.max(transform.scale.y);
+        let place = |point: Vec2| {
+            (transform.translation + transform.rotation * (point * scale).extend(0.0)).truncate()
+        };
+        Capsule {
+            a: place(self.a),
+            b: place(self.b),
+            radius: self.radius * scale,
+        }
+    }
+
+    fn overlaps(&self, other: &Capsule) -> bool {
+        let reach = self.radius + other.radius;
+        let (closest, other_closest) = closest_points(self.a, self.b, other.a, other.b);
+        closest.distance_squared(other_closest) < reach * reach
+    }
+
+    /// Direction from this capsule to the other and how deep they overlap,
+    /// if they do
+    fn contact(&self, other: &Capsule) -> Option<(Vec2, f32)> {
+        let (closest, other_closest) = closest_points(self.a, self.b, other.a, other.b);
+        let offset = other_closest - closest;
+        let distance = offset.length();
+        let depth = self.radius + other.radius - distance;
+        if depth <= 0.0 {
+            return None;
+        }
+        let normal = if distance > f32::EPSILON {
+            offset / distance
+        } else {
+            Vec2::Y
+        };
+        Some((normal, depth))
+    }
+}
+
+/// Body, neck, horn and tail of caticorn.png
+pub const CATICORN_SHAPE: &[Capsule] = &[
+    Capsule::new(Vec2::new(-22.5, -17.5), Vec2::new(10.5, -17.5), 19.0),
+    Capsule::new(Vec2::new(-20.0, 10.0), Vec2::new(6.0, 22.0), 12.0),
+    Capsule::new(Vec2::new(20.5, 32.0), Vec2::new(20.5, 24.0), 5.0),
+    Capsule::new(Vec2::new(26.0, -27.0), Vec2::new(36.0, -27.0), 5.0),
+];
+
+/// The slightly squashed ring of donut.png
+pub const CANDY_SHAPE: &[Capsule] = &[Capsule::new(
+    Vec2::new(-5.0, 0.0),
+    Vec2::new(5.0, 0.0),
+    19.5,
+)];
+
+/// A power-up glyph
+pub const POWER_UP_SHAPE: &[Capsule] = &[Capsule::circle(Vec2::ZERO, 20.0)];
+
+#[derive(Component, Clone, Copy)]
+pub struct Collider(pub &'static [Capsule]);
+
+impl Collider {
+    pub fn overlaps(
+        &self,
+        transform: &Transform,
+        other: &Collider,
+        other_transform: &Transform,
+    ) -> bool {
+        self.0.iter().any(|capsule| {
+            let capsule = capsule.placed(transform);
+            other
+                .0
+                .iter()
+                .any(|other| capsule.overlaps(&other.placed(other_transform)))
+        })
+    }
+
+    /// Direction from this entity to the other and how deep they overlap,
+    /// where they overlap the most
+    pub fn contact(
+        &self,
+        transform: &Transform,
+        other: &Collider,
+        other_transform: &Transform,
+    ) -> Option<(Vec2, f32)> {
+        self.0
+            .iter()
+            .flat_map(|capsule| {
+                let capsule = capsule.placed(transform);
+                other
+                    .0
+                    .iter()
+                    .filter_map(move |other| capsule.contact(&other.placed(other_transform)))
+            })
+            .max_by(|(_, depth), (_, other_depth)| depth.total_cmp(other_depth))
+    }
+
+    /// How far from the entity's translation the shape reaches
+    pub fn reach(&self, transform: &Transform) -> f32 {
+        let scale = transform.scale.x.max(transform.scale.y);
+        self.0
+            .iter()
+            .map(|capsule| capsule.a.length().max(capsule.b.length()) + capsule.radius)
+            .fold(0.0, f32::max)
+            * scale
+    }
+}
+
+/// The points of the segments a0-a1 and b0-b1 closest to each other
+fn closest_points(a0: Vec2, a1: Vec2, b0: Vec2, b1: Vec2) -> (Vec2, Vec2) {
+    let da = a1 - a0;
+    let db = b1 - b0;
+    let r = a0 - b0;
+    let len_a = da.length_squared();
+    let len_b = db.length_squared();
+    let f = db.dot(r);
+
+    let (s, t) = if len_a <= f32::EPSILON && len_b <= f32::EPSILON {
+        (0.0, 0.0)
+    } else if len_a <= f32::EPSILON {
+        (0.0, (f / len_b).clamp(0.0, 1.0))
+    } else {
+        let c = da.dot(r);
+        if len_b <= f32::EPSILON {
+            ((-c / len_a).clamp(0.0, 1.0), 0.0)
+        } else {
+            let b = da.dot(db);
+            let denominator = len_a * len_b - b * b;
+            // Parallel segments have no single closest pair, any s will do
+            let mut s = if denominator > f32::EPSILON {
+                ((b * f - c * len_b) / denominator).clamp(0.0, 1.0)
+            } else {
+                0.0
+            };
+            let mut t = (b * s + f) / len_b;
+            if t < 0.0 {
+                t = 0.0;
+                s = (-c / len_a).clamp(0.0, 1.0);
+            } else if t > 1.0 {
+                t = 1.0;
+                s = ((b - c) / len_a).clamp(0.0, 1.0);
+            }
+            (s, t)
+        }
+    };
+
+    (a0 + da * s, b0 + db * t)
+}
+
+/// Side of a grid cell, about the size of a candy
+pub const GRID_CELL_SIZE: f32 = 64.0;
+/// How far candy may get nudged in a step after it has been indexed
+const INDEX_SLACK: f32 = 16.0;
+
+/// Entities bucketed by where they are, so finding the ones near a spot
+/// doesn't mean looking at all of them
+pub struct Grid {
+    cell_size: f32,
+    cells: HashMap<IVec2, Vec<Entity>>,
+    /// Reach of the largest entity in the grid
+    max_reach: f32,
+}
+
+impl Grid {
+    pub fn new(cell_size: f32) -> Self {
+        Grid {
+            cell_size,
+            cells: HashMap::default(),
+            max_reach: 0.0,
+        }
+    }
+
+    fn cell(&self, position: Vec2) -> IVec2 {
+        (position / self.cell_size).floor().as_ivec2()
+    }
+
+    /// Empties the grid, keeping the cells around for the next fill
+    pub fn clear(&mut self) {
+        self.cells.retain(|_, entities| !entities.is_empty());
+        for entities in self.cells.values_mut() {
+            entities.clear();
+        }
+        self.max_reach = 0.0;
+    }
+
+    /// Files the entity under the cell its translation is in
+    pub fn insert(&mut self, entity: Entity, position: Vec2, reach: f32) {
+        let cell = self.cell(position);
+        self.cells.entry(cell).or_default().push(entity);
+        self.max_reach = self.max_reach.max(reach);
+    }
+
+    /// Every entity that might reach within `reach` of `position`, each once,
+    /// always in the same order for the same grid
+    pub fn near(&self, position: Vec2, reach: f32) -> impl Iterator<Item = Entity> + '_ {
+        let reach = Vec2::splat(reach + self.max_reach);
+        let min = self.cell(position - reach);
+        let max = self.cell(position + reach);
+        (min.y..=max.y)
+            .flat_map(move |y| (min.x..=max.x).map(move |x| IVec2::new(x, y)))
+            .filter_map(|cell| self.cells.get(&cell))
+            .flatten()
+            .copied()
+    }
+}
+
+/// Where every candy is, filed once per step after candy has moved
+#[derive(Resource)]
+pub struct SpatialIndex {
+    pub candy: Grid,
+}
+
+impl Default for SpatialIndex {
+    fn default() -> Self {
+        SpatialIndex {
+            candy: Grid::new(GRID_CELL_SIZE),
+        }
+    }
+}
+
+pub fn gameplay_index_candy(
+    mut index: ResMut<SpatialIndex>,
+    query: Query<(Entity, &Collider, &Transform), With<Candy>>,
+) {
+    index.candy.clear();
+    for (entity, collider, transform) in &query {
+        let reach = collider.reach(transform) + INDEX_SLACK;
+        index
+            .candy
+            .insert(entity, transform.translation.truncate(), reach);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_near(actual: Vec2, expected: Vec2) {
+        assert!(
+            actual.distance(expected) < 1e-5,
+            "{actual} is not {expected}"
+        );
+    }
+
+    #[test]
+    fn crossing_segments_meet() {
+        let (a, b) = closest_points(
+            Vec2::new(-1.0, 0.0),
+            Vec2::new(1.0, 0.0),
+            Vec2::new(0.0, -1.0),
+            Vec2::new(0.0, 1.0),
+        );
+        assert_near(a, Vec2::ZERO);
+        assert_near(b, Vec2::ZERO);
+    }
+
+    #[test]
+    fn end_closest_to_the_middle_of_the_other() {
+        let (a, b) = closest_points(
+            Vec2::new(0.0, 0.0),
+            Vec2::new(4.0, 0.0),
+            Vec2::new(2.0, 3.0),
+            Vec2::new(2.0, 1.0),
+        );
+        assert_near(a, Vec2::new(2.0, 0.0));
+        assert_near(b, Vec2::new(2.0, 1.0));
+    }
+
+    #[test]
+    fn ends_closest_to_each_other() {
+        let (a, b) = closest_points(
+            Vec2::new(0.0, 0.0),
+            Vec2::new(1.0, 0.0),
+            Vec2::new(3.0, 1.0),
+            Vec2::new(5.0, 2.0),
+        );
+        assert_near(a, Vec2::new(1.0, 0.0));
+        assert_near(b, Vec2::new(3.0, 1.0));
+    }
+
+    #[test]
+    fn parallel_segments() {
+        let (a, b) = closest_points(
+            Vec2::new(0.0, 0.0),
+            Vec2::new(2.0, 0.0),
+            Vec2::new(1.0, 1.0),
+            Vec2::new(3.0, 1.0),
+        );
+        assert!((a.distance(b) - 1.0).abs() < 1e-5);
+
+        let (a, b) = closest_points(
+            Vec2::new(0.0, 0.0),
+            Vec2::new(1.0, 0.0),
+            Vec2::new(3.0, 0.0),
+            Vec2::new(5.0, 0.0),
+        );
+        assert_near(a, Vec2::new(1.0, 0.0));
+        assert_near(b, Vec2::new(3.0, 0.0));
+    }
+
+    #[test]
+    fn points_are_segments_too() {
+        let (a, b) = closest_points(
+            Vec2::new(1.0, 1.0),
+            Vec2::new(1.0, 1.0),
+            Vec2::new(0.0, 0.0),
+            Vec2::new(2.0, 0.0),
+        );
+        assert_near(a, Vec2::new(1.0, 1.0));
+        assert_near(b, Vec2::new(1.0, 0.0));
+
+        let (a, b) = closest_points(
+            Vec2::new(0.0, 0.0),
+            Vec2::new(2.0, 0.0),
+            Vec2::new(3.0, 4.0),
+            Vec2::new(3.0, 4.0),
+        );
+        assert_near(a, Vec2::new(2.0, 0.0));
+        assert_near(b, Vec2::new(3.0, 4.0));
+
+        let (a, b) = closest_points(
+            Vec2::new(1.0, 2.0),
+            Vec2::new(1.0, 2.0),
+            Vec2::new(4.0, 6.0),
+            Vec2::new(4.0, 6.0),
+        );
+        assert_near(a, Vec2::new(1.0, 2.0));
+        assert_near(b, Vec2::new(4.0, 6.0));
+    }
+
+    fn grid() -> Grid {
+        let mut grid = Grid::new(GRID_CELL_SIZE);
+        grid.insert(Entity::from_raw(0), Vec2::new(0.0, 0.0), 20.0);
+        grid.insert(Entity::from_raw(1), Vec2::new(100.0, 0.0), 20.0);
+        grid.insert(Entity::from_raw(2), Vec2::new(10.0, 5.0), 20.0);
+        grid.insert(Entity::from_raw(3), Vec2::new(1000.0, -1000.0), 20.0);
+        grid
+    }
+
+    #[test]
+    fn near_finds_what_might_reach() {
+        let grid = grid();
+        let mut near: Vec<Entity> = grid.near(Vec2::new(0.0, 0.0), 20.0).collect();
+        near.sort();
+        assert_eq!(near, [Entity::from_raw(0), Entity::from_raw(2)]);
+
+        let mut near: Vec<Entity> = grid.near(Vec2::new(70.0, 0.0), 20.0).collect();
+        near.sort();
+        assert_eq!(
+            near,
+            [
+                Entity::from_raw(0),
+                Entity::from_raw(1),
+                Entity::from_raw(2)
+            ]
+        );
+    }
+
+    #[test]
+    fn near_lists_each_entity_once() {
+        let grid = grid();
+        let mut near: Vec<Entity> = grid.near(Vec2::ZERO, 2000.0).collect();
+        near.sort();
+        assert_eq!(near, (0..4).map(Entity::from_raw).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn near_keeps_its_order() {
+        let grid = grid();
+        let first: Vec<Entity> = grid.near(Vec2::ZERO, 2000.0).collect();
+        let second: Vec<Entity> = grid.near(Vec2::ZERO, 2000.0).collect();
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn cleared_grid_is_empty() {
+        let mut grid = grid();
+        grid.clear();
+        assert_eq!(grid.near(Vec2::ZERO, 2000.0).count(), 0);
+        grid.insert(Entity::from_raw(4), Vec2::new(10.0, 10.0), 20.0);
+        assert_eq!(
+            grid.near(Vec2::ZERO, 20.0).collect::<Vec<_>>(),
+            [Entity::from_raw(4)]
+        );
+    }
+}
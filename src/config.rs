@@ -0,0 +1,180 @@
+//! Numbers that set how the game feels, loaded from
+//! `assets/config/game.config.ron` and picked up again whenever the file
+//! changes while the game runs. Until it has loaded, or if it can't be, the
+//! game uses the values built in here.
+//!
+//! A round keeps the config it started with, so that a change takes effect
+//! from the next round on and a round can be replayed with the numbers it was
+//! played with.
+
+use std::collections::BTreeMap;
+
+use bevy::asset::{AssetLoader, LoadContext, LoadedAsset};
+use bevy::prelude::*;
+use bevy::reflect::{TypePath, TypeUuid};
+use bevy::utils::BoxedFuture;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::candy::CandyKind;
+use crate::network::NetworkSession;
+use crate::replay::ReplayPlayback;
+
+pub const CONFIG_ASSET: &str = "config/game.config.ron";
+
+/// The config of the round being played
+#[derive(Resource, Serialize, Deserialize, TypeUuid, TypePath, Clone, Debug)]
+#[uuid = "c2f7d6a1-8e3b-4f0a-b5c9-3d1e6a7f2b84"]
+#[serde(default)]
+pub struct GameConfig {
+    /// Pixels per second
+    pub player_speed: f32,
+    /// Candy closer than this to a caticorn is pushed away
+    pub push_range: f32,
+    /// Push on candy right next to a caticorn, less the distance to it
+    pub push_force: f32,
+    /// Like `push_range`, for candy that runs from caticorns
+    pub flee_range: f32,
+    /// Like `push_force`, for candy that runs from caticorns
+    pub flee_force: f32,
+    /// Change of the caticorn's scale when it eats each kind of candy
+    pub growth: BTreeMap<CandyKind, f32>,
+    pub min_scale: f32,
+    pub max_scale: f32,
+}
+
+impl Default for GameConfig {
+    fn default() -> Self {
+        GameConfig {
+            player_speed: 600.0,
+            push_range: 200.0,
+            push_force: 400.0,
+            flee_range: 300.0,
+            flee_force: 700.0,
+            growth: BTreeMap::from([
+                (CandyKind::Donut, 0.03),
+                (CandyKind::Sprinkles, 0.03),
+                (CandyKind::Licorice, 0.03),
+                (CandyKind::Gumdrop, 0.05),
+                (CandyKind::Rotten, -0.1),
+            ]),
+            min_scale: 1.0,
+            max_scale: 6.0,
+        }
+    }
+}
+
+impl GameConfig {
+    pub fn growth(&self, kind: CandyKind) -> f32 {
+        self.growth.get(&kind).copied().unwrap_or(0.0)
+    }
+
+    /// Reads the config straight from the assets folder, for when there is
+    /// no asset server
+    pub fn read() -> Option<GameConfig> {
+        read_asset(CONFIG_ASSET)
+    }
+}
+
+/// Reads a RON asset straight from the assets folder
+#[cfg_attr(target_arch = "wasm32", allow(unused_variables))]
+pub fn read_asset<T: DeserializeOwned>(asset: &str) -> Option<T> {
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        let path = bevy::asset::FileAssetIo::get_base_path()
+            .join("assets")
+            .join(asset);
+        let data = match std::fs::read(&path) {
+            Ok(data) => data,
+            Err(e) => {
+                warn!("failed to read {}: {e}", path.display());
+                return None;
+            }
+        };
+        match ron::de::from_bytes::<T>(&data) {
+            Ok(value) => Some(value),
+            Err(e) => {
+                warn!("ignoring broken {}: {e}", path.display());
+                None
+            }
+        }
+    }
+    #[cfg(target_arch = "wasm32")]
+    None
+}
+
+#[derive(Default)]
+pub struct GameConfigLoader;
+
+impl AssetLoader for GameConfigLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), bevy::asset::Error>> {
+        Box::pin(async move {
+            let config = ron::de::from_bytes::<GameConfig>(bytes)?;
+            load_context.set_default_asset(LoadedAsset::new(config));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["config.ron"]
+    }
+}
+
+#[derive(Resource)]
+pub struct GameConfigHandle(Handle<GameConfig>);
+
+/// The config the next round starts with
+#[derive(Resource, Default)]
+pub struct NextGameConfig(pub GameConfig);
+
+pub fn config_setup(mut commands: Commands, asset_server: Res<AssetServer>) {
+    info!("config_setup");
+
+    commands.insert_resource(GameConfigHandle(asset_server.load(CONFIG_ASSET)));
+}
+
+/// Takes the config over from the asset server each time it (re)loads
+pub fn config_update(
+    mut next_config: ResMut<NextGameConfig>,
+    handle: Res<GameConfigHandle>,
+    mut events: EventReader<AssetEvent<GameConfig>>,
+    configs: Res<Assets<GameConfig>>,
+) {
+    for event in events.iter() {
+        match event {
+            AssetEvent::Created { handle: changed } | AssetEvent::Modified { handle: changed }
+                if *changed == handle.0 =>
+            {
+                if let Some(loaded) = configs.get(&handle.0) {
+                    info!("loaded {CONFIG_ASSET}");
+                    next_config.0 = loaded.clone();
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Fixes the config for the round about to start. Replays use the one they
+/// were recorded with, and online rounds the built-in one, which is the same
+/// for every caticorn in the round.
+pub fn config_start_round(
+    mut config: ResMut<GameConfig>,
+    next_config: Res<NextGameConfig>,
+    playback: Option<Res<ReplayPlayback>>,
+    online: Option<Res<NetworkSession>>,
+) {
+    info!("config_start_round");
+
+    *config = if let Some(playback) = playback {
+        playback.config().clone()
+    } else if online.is_some() {
+        GameConfig::default()
+    } else {
+        next_config.0.clone()
+    };
+}
@@ -0,0 +1,246 @@
+use std::collections::BTreeMap;
+
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::input::{PlayerGamepads, MAX_PLAYERS};
+use crate::storage;
+
+const CONTROLS_KEY: &str = "controls.ron";
+
+/// Everything the player can ask the game to do, independent of the key or
+/// button it is bound to
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum Action {
+    MoveUp,
+    MoveDown,
+    MoveLeft,
+    MoveRight,
+    Player2MoveUp,
+    Player2MoveDown,
+    Player2MoveLeft,
+    Player2MoveRight,
+    Start,
+    StartTwoPlayers,
+    /// Opens the pause menu, or leaves an online round
+    #[serde(alias = "Quit")]
+    Pause,
+    Settings,
+    ForceEnd,
+    DebugSpawn,
+    DebugGrow,
+}
+
+impl Action {
+    /// Up, down, left and right of each player, by player index
+    pub const MOVEMENT: [[Action; 4]; MAX_PLAYERS] = [
+        [
+            Action::MoveUp,
+            Action::MoveDown,
+            Action::MoveLeft,
+            Action::MoveRight,
+        ],
+        [
+            Action::Player2MoveUp,
+            Action::Player2MoveDown,
+            Action::Player2MoveLeft,
+            Action::Player2MoveRight,
+        ],
+    ];
+
+    fn is_debug(self) -> bool {
+        matches!(
+            self,
+            Action::ForceEnd | Action::DebugSpawn | Action::DebugGrow
+        )
+    }
+
+    /// Whose gamepad the buttons bound to this action are read from
+    fn player(self) -> usize {
+        match self {
+            Action::Player2MoveUp
+            | Action::Player2MoveDown
+            | Action::Player2MoveLeft
+            | Action::Player2MoveRight => 1,
+            _ => 0,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Default, Debug)]
+#[serde(default)]
+pub struct Binding {
+    pub keys: Vec<KeyCode>,
+    pub gamepad_buttons: Vec<GamepadButtonType>,
+}
+
+impl Binding {
+    fn new(keys: &[KeyCode], gamepad_buttons: &[GamepadButtonType]) -> Self {
+        Binding {
+            keys: keys.to_vec(),
+            gamepad_buttons: gamepad_buttons.to_vec(),
+        }
+    }
+}
+
+/// Which keys and gamepad buttons trigger each action, loaded from
+/// `controls.ron` next to the high scores. The file is written with the
+/// defaults on first run so there is something to edit.
+#[derive(Resource, Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct ActionMap {
+    /// The debug actions are ignored unless this is set, which it is only in
+    /// debug builds. It isn't kept in the controls file, so that a file
+    /// written by a debug build doesn't turn them on in a release build.
+    #[serde(skip)]
+    pub debug_actions: bool,
+    pub bindings: BTreeMap<Action, Binding>,
+}
+
+impl Default for ActionMap {
+    fn default() -> Self {
+        use GamepadButtonType as Button;
+
+        ActionMap {
+            debug_actions: cfg!(debug_assertions),
+            bindings: BTreeMap::from([
+                (
+                    Action::MoveUp,
+                    Binding::new(&[KeyCode::Up, KeyCode::W], &[Button::DPadUp]),
+                ),
+                (
+                    Action::MoveDown,
+                    Binding::new(&[KeyCode::Down, KeyCode::S], &[Button::DPadDown]),
+                ),
+                (
+                    Action::MoveLeft,
+                    Binding::new(&[KeyCode::Left, KeyCode::A], &[Button::DPadLeft]),
+                ),
+                (
+                    Action::MoveRight,
+                    Binding::new(&[KeyCode::Right, KeyCode::D], &[Button::DPadRight]),
+                ),
+                (
+                    Action::Player2MoveUp,
+                    Binding::new(&[KeyCode::I], &[Button::DPadUp]),
+                ),
+                (
+                    Action::Player2MoveDown,
+                    Binding::new(&[KeyCode::K], &[Button::DPadDown]),
+                ),
+                (
+                    Action::Player2MoveLeft,
+                    Binding::new(&[KeyCode::J], &[Button::DPadLeft]),
+                ),
+                (
+                    Action::Player2MoveRight,
+                    Binding::new(&[KeyCode::L], &[Button::DPadRight]),
+                ),
+                (
+                    Action::Start,
+                    Binding::new(&[KeyCode::Space], &[Button::South]),
+                ),
+                (
+                    Action::StartTwoPlayers,
+                    Binding::new(&[KeyCode::Key2], &[Button::North]),
+                ),
+                (
+                    Action::Pause,
+                    Binding::new(&[KeyCode::Escape], &[Button::Start]),
+                ),
+                (
+                    Action::Settings,
+                    Binding::new(&[KeyCode::Tab], &[Button::Select]),
+                ),
+                (Action::ForceEnd, Binding::new(&[KeyCode::Return], &[])),
+                (Action::DebugSpawn, Binding::new(&[KeyCode::O], &[])),
+                (Action::DebugGrow, Binding::new(&[KeyCode::P], &[])),
+            ]),
+        }
+    }
+}
+
+impl ActionMap {
+    pub fn load() -> Self {
+        let Some(data) = storage::load(CONTROLS_KEY) else {
+            let action_map = ActionMap::default();
+            action_map.save();
+            return action_map;
+        };
+        match ron::from_str::<ActionMap>(&data) {
+            Ok(mut action_map) => {
+                // Actions added since the file was written keep their defaults
+                for (action, binding) in ActionMap::default().bindings {
+                    action_map.bindings.entry(action).or_insert(binding);
+                }
+                action_map
+            }
+            Err(e) => {
+                warn!("ignoring broken controls file, using the default controls: {e}");
+                ActionMap::default()
+            }
+        }
+    }
+
+    pub fn save(&self) {
+        let data = match ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default()) {
+            Ok(data) => data,
+            Err(e) => {
+                error!("failed to serialize controls: {e}");
+                return;
+            }
+        };
+        if let Err(e) = storage::save(CONTROLS_KEY, &data) {
+            error!("failed to save controls: {e}");
+        }
+    }
+
+    fn binding(&self, action: Action) -> Option<&Binding> {
+        if action.is_debug() && !self.debug_actions {
+            return None;
+        }
+        self.bindings.get(&action)
+    }
+}
+
+/// Looks up actions in the [`ActionMap`] against the keyboard and gamepads
+#[derive(SystemParam)]
+pub struct Actions<'w> {
+    action_map: Res<'w, ActionMap>,
+    keyboard_input: Res<'w, Input<KeyCode>>,
+    gamepad_buttons: Res<'w, Input<GamepadButton>>,
+    player_gamepads: Res<'w, PlayerGamepads>,
+}
+
+impl<'w> Actions<'w> {
+    /// Held on the keyboard or on the gamepad of the player the action
+    /// belongs to
+    pub fn pressed(&self, action: Action) -> bool {
+        let Some(binding) = self.action_map.binding(action) else {
+            return false;
+        };
+        self.keyboard_input
+            .any_pressed(binding.keys.iter().copied())
+            || self.player_gamepads.0[action.player()].is_some_and(|gamepad| {
+                binding.gamepad_buttons.iter().any(|&button_type| {
+                    self.gamepad_buttons
+                        .pressed(GamepadButton::new(gamepad, button_type))
+                })
+            })
+    }
+
+    /// Pressed this frame on the keyboard or on any connected gamepad, so
+    /// that everyone on the couch can work the menus
+    pub fn just_pressed(&self, action: Action) -> bool {
+        let Some(binding) = self.action_map.binding(action) else {
+            return false;
+        };
+        self.keyboard_input
+            .any_just_pressed(binding.keys.iter().copied())
+            || self
+                .gamepad_buttons
+                .get_just_pressed()
+                .any(|button| binding.gamepad_buttons.contains(&button.button_type))
+    }
+}
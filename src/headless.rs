@@ -0,0 +1,346 @@
+use std::time::Duration;
+
+use bevy::app::AppExit;
+use bevy::log::LogPlugin;
+use bevy::prelude::*;
+use bevy::time::TimeUpdateStrategy;
+use bevy::utils::Instant;
+
+use crate::collision::{self, Collider};
+use crate::config::{GameConfig, NextGameConfig};
+use crate::levels::{Level, LevelSet, Levels};
+use crate::powerup::{PowerUpFont, PowerUps};
+use crate::timestep::{Interpolated, SimulationTime, FIXED_TIMESTEP_SECONDS};
+use crate::{
+    BodySize, Candy, CandyImage, GameOverReason, GameState, InputSet, Player, PlayerControl,
+    PlayerImage, RoundStats, Score, PLAYER_SIZE,
+};
+
+struct RoundResult {
+    level: usize,
+    cleared: bool,
+    points: u32,
+    candies_eaten: u32,
+    duration: f32,
+    final_scale: f32,
+    timed_out: bool,
+    game_over: Option<GameOverReason>,
+}
+
+#[derive(Resource)]
+struct HeadlessRun {
+    rounds: usize,
+    max_round_seconds: f32,
+    timed_out: bool,
+    results: Vec<RoundResult>,
+}
+
+/// Runs `rounds` rounds back to back on simulated time, one fixed update per
+/// app update as fast as the CPU allows, and prints a summary when done.
+pub fn run(rounds: usize, max_round_seconds: f32, seed: Option<u64>) {
+    let mut app = App::new();
+
+    app.add_plugins(MinimalPlugins)
+        .add_plugins(LogPlugin {
+            filter: "caticorn=info".into(),
+            level: bevy::log::Level::WARN,
+        })
+        .insert_resource(TimeUpdateStrategy::ManualDuration(Duration::from_secs_f32(
+            FIXED_TIMESTEP_SECONDS,
+        )))
+        .insert_resource(PlayerImage(Handle::default()))
+        .insert_resource(CandyImage(Handle::default()))
+        .insert_resource(PowerUpFont(Handle::default()))
+        .insert_resource(HeadlessRun {
+            rounds,
+            max_round_seconds,
+            timed_out: false,
+            results: Vec::with_capacity(rounds),
+        });
+
+    crate::add_gameplay(&mut app, seed);
+    app.insert_resource(Levels::new(LevelSet::read()))
+        .insert_resource(NextGameConfig(GameConfig::read().unwrap_or_default()));
+
+    app.add_systems(Startup, headless_setup)
+        .add_systems(
+            FixedUpdate,
+            (headless_bot.in_set(InputSet), headless_round_timeout)
+                .run_if(in_state(GameState::Playing)),
+        )
+        .add_systems(OnEnter(GameState::End), headless_round_finished)
+        .add_systems(OnEnter(GameState::GameOver), headless_round_finished);
+
+    app.run();
+}
+
+#[derive(Resource)]
+struct Bench {
+    steps: usize,
+    step_started: Option<Instant>,
+    step_times: Vec<Duration>,
+    candies: Vec<usize>,
+}
+
+/// Simulates a level crammed with `candies` candies for `steps` fixed
+/// updates, and prints how long each update took against the 60 fps budget.
+/// Nothing gets drawn, so this is the simulation alone.
+pub fn bench(candies: usize, steps: usize, seed: Option<u64>) {
+    let mut app = App::new();
+
+    app.add_plugins(MinimalPlugins)
+        .add_plugins(LogPlugin {
+            filter: "caticorn=info".into(),
+            level: bevy::log::Level::WARN,
+        })
+        .insert_resource(TimeUpdateStrategy::ManualDuration(Duration::from_secs_f32(
+            FIXED_TIMESTEP_SECONDS,
+        )))
+        .insert_resource(PlayerImage(Handle::default()))
+        .insert_resource(CandyImage(Handle::default()))
+        .insert_resource(PowerUpFont(Handle::default()))
+        .insert_resource(Bench {
+            steps,
+            step_started: None,
+            step_times: Vec::with_capacity(steps),
+            candies: Vec::with_capacity(steps),
+        });
+
+    crate::add_gameplay(&mut app, seed);
+    // A level that never ends, and doesn't add much candy on its own
+    let level = Level {
+        name: "benchmark".to_string(),
+        candy_spawn_seconds: 3600.0,
+        initial_candies: candies,
+        max_candy: usize::MAX,
+        target: u32::MAX,
+        time_limit_seconds: f32::MAX,
+        ..default()
+    };
+    app.insert_resource(Levels::new(Some(LevelSet {
+        levels: vec![level],
+    })))
+    .insert_resource(NextGameConfig(GameConfig::read().unwrap_or_default()));
+
+    app.add_systems(Startup, headless_setup)
+        .add_systems(
+            FixedUpdate,
+            headless_bot
+                .in_set(InputSet)
+                .run_if(in_state(GameState::Playing)),
+        )
+        .add_systems(First, bench_start_step.run_if(in_state(GameState::Playing)))
+        .add_systems(Last, bench_end_step.run_if(in_state(GameState::Playing)));
+
+    app.run();
+}
+
+fn bench_start_step(mut bench: ResMut<Bench>) {
+    bench.step_started = Some(Instant::now());
+}
+
+fn bench_end_step(
+    mut bench: ResMut<Bench>,
+    candy_query: Query<(), With<Candy>>,
+    mut exit: EventWriter<AppExit>,
+) {
+    let Some(started) = bench.step_started.take() else {
+        return;
+    };
+    bench.step_times.push(started.elapsed());
+    bench.candies.push(candy_query.iter().len());
+
+    if bench.step_times.len() >= bench.steps {
+        print_bench_summary(&bench);
+        exit.send(AppExit);
+    }
+}
+
+fn print_bench_summary(bench: &Bench) {
+    let mut step_times: Vec<f32> = bench
+        .step_times
+        .iter()
+        .map(|time| time.as_secs_f32() * 1000.0)
+        .collect();
+    step_times.sort_by(f32::total_cmp);
+    let steps = step_times.len().max(1);
+    let percentile = |p: usize| {
+        step_times
+            .get((steps - 1) * p / 100)
+            .copied()
+            .unwrap_or(0.0)
+    };
+    let budget = 1000.0 / 60.0;
+
+    println!("steps:        {}", step_times.len());
+    println!(
+        "candy:        min {} max {}",
+        bench.candies.iter().min().unwrap_or(&0),
+        bench.candies.iter().max().unwrap_or(&0)
+    );
+    println!(
+        "step (ms):    mean {:.3} median {:.3} p99 {:.3} max {:.3}",
+        step_times.iter().sum::<f32>() / steps as f32,
+        percentile(50),
+        percentile(99),
+        percentile(100)
+    );
+    println!(
+        "over budget:  {} of {} steps took longer than {:.1} ms",
+        step_times.iter().filter(|&&time| time > budget).count(),
+        step_times.len(),
+        budget
+    );
+}
+
+fn headless_setup(mut commands: Commands, mut next_state: ResMut<NextState<GameState>>) {
+    info!("headless_setup");
+
+    commands.spawn((
+        TransformBundle::default(),
+        Player { index: 0 },
+        PlayerControl::default(),
+        BodySize(PLAYER_SIZE),
+        Collider(collision::CATICORN_SHAPE),
+        Interpolated::new(Vec3::ZERO),
+        Score::default(),
+        PowerUps::default(),
+    ));
+
+    next_state.set(GameState::Playing);
+}
+
+/// Steers the caticorn straight at the nearest candy worth eating
+fn headless_bot(
+    mut player_query: Query<(&Transform, &mut PlayerControl), With<Player>>,
+    candy_query: Query<(&Transform, &Candy), Without<Player>>,
+) {
+    let Ok((transform, mut control)) = player_query.get_single_mut() else {
+        return;
+    };
+    let position = transform.translation.truncate();
+
+    control.direction = candy_query
+        .iter()
+        .filter(|(_, candy)| candy.kind.properties().points > 0)
+        .map(|(candy_transform, _)| candy_transform.translation.truncate())
+        .min_by(|a, b| {
+            a.distance_squared(position)
+                .total_cmp(&b.distance_squared(position))
+        })
+        .map(|target| (target - position).normalize_or_zero())
+        .unwrap_or(Vec2::ZERO);
+}
+
+fn headless_round_timeout(
+    time: Res<SimulationTime>,
+    round_stats: Res<RoundStats>,
+    mut run: ResMut<HeadlessRun>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    if time.elapsed_seconds() - round_stats.start_time > run.max_round_seconds {
+        run.timed_out = true;
+        next_state.set(GameState::End);
+    }
+}
+
+fn headless_round_finished(
+    mut player_query: Query<(&mut Transform, &Score), With<Player>>,
+    round_stats: Res<RoundStats>,
+    mut levels: ResMut<Levels>,
+    mut run: ResMut<HeadlessRun>,
+    mut next_state: ResMut<NextState<GameState>>,
+    mut exit: EventWriter<AppExit>,
+) {
+    let Ok((mut transform, score)) = player_query.get_single_mut() else {
+        return;
+    };
+
+    let cleared = score
+        .candies_eaten
+        .saturating_sub(round_stats.candies_at_start)
+        >= levels.current().target;
+    let result = RoundResult {
+        level: round_stats.level,
+        cleared,
+        points: score.points,
+        candies_eaten: score.candies_eaten,
+        duration: round_stats.duration,
+        final_scale: transform.scale.x,
+        timed_out: run.timed_out,
+        game_over: round_stats.game_over,
+    };
+    info!(
+        "round {} (seed {}): level {}{} score {} candy {} scale {:.2} duration {:.1}s{}",
+        run.results.len() + 1,
+        round_stats.seed,
+        result.level + 1,
+        if result.cleared { " cleared" } else { "" },
+        result.points,
+        result.candies_eaten,
+        result.final_scale,
+        result.duration,
+        match (result.timed_out, result.game_over) {
+            (true, _) => " (timed out)".to_string(),
+            (false, Some(reason)) => format!(" (game over: {})", reason.describe()),
+            (false, None) => String::new(),
+        },
+    );
+    run.results.push(result);
+    run.timed_out = false;
+
+    transform.translation = Vec3::ZERO;
+
+    // Like the game, carry on with the next level or start a new run
+    if !cleared || !levels.advance() {
+        levels.select(0);
+    }
+
+    if run.results.len() < run.rounds {
+        next_state.set(GameState::Playing);
+    } else {
+        print_summary(&run.results);
+        exit.send(AppExit);
+    }
+}
+
+fn print_summary(results: &[RoundResult]) {
+    let rounds = results.len().max(1) as f32;
+    let mean = |value: fn(&RoundResult) -> f32| results.iter().map(value).sum::<f32>() / rounds;
+    let max = |value: fn(&RoundResult) -> f32| results.iter().map(value).fold(0.0, f32::max);
+
+    println!("rounds:       {}", results.len());
+    println!(
+        "timed out:    {}",
+        results.iter().filter(|r| r.timed_out).count()
+    );
+    println!(
+        "game over:    {}",
+        results.iter().filter(|r| r.game_over.is_some()).count()
+    );
+    println!(
+        "cleared:      {} (highest level {})",
+        results.iter().filter(|r| r.cleared).count(),
+        results.iter().map(|r| r.level + 1).max().unwrap_or(0)
+    );
+    println!(
+        "score:        mean {:.1} max {:.0}",
+        mean(|r| r.points as f32),
+        max(|r| r.points as f32)
+    );
+    println!(
+        "candy eaten:  mean {:.1} max {:.0}",
+        mean(|r| r.candies_eaten as f32),
+        max(|r| r.candies_eaten as f32)
+    );
+    println!(
+        "final scale:  mean {:.2} max {:.2}",
+        mean(|r| r.final_scale),
+        max(|r| r.final_scale)
+    );
+    println!(
+        "duration (s): mean {:.1} max {:.1}",
+        mean(|r| r.duration),
+        max(|r| r.duration)
+    );
+}
@@ -0,0 +1,215 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::controls::{Action, Actions};
+use crate::{input, storage, GameState, Text};
+
+pub const MAX_HIGH_SCORES: usize = 10;
+const NAME_LENGTH: usize = 3;
+const DEFAULT_NAME: &str = "CAT";
+const HIGH_SCORES_KEY: &str = "highscores.ron";
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct HighScoreEntry {
+    pub name: String,
+    pub points: u32,
+    pub candies_eaten: u32,
+    pub final_scale: f32,
+    pub duration_seconds: f32,
+    pub date: String,
+}
+
+#[derive(Resource, Serialize, Deserialize, Default, Debug)]
+pub struct HighScores {
+    pub entries: Vec<HighScoreEntry>,
+}
+
+impl HighScores {
+    pub fn load() -> Self {
+        let Some(data) = storage::load(HIGH_SCORES_KEY) else {
+            return HighScores::default();
+        };
+        match ron::from_str::<HighScores>(&data) {
+            Ok(high_scores) => high_scores,
+            Err(e) => {
+                warn!("ignoring broken high score table: {e}");
+                HighScores::default()
+            }
+        }
+    }
+
+    pub fn save(&self) {
+        let data = match ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default()) {
+            Ok(data) => data,
+            Err(e) => {
+                error!("failed to serialize high scores: {e}");
+                return;
+            }
+        };
+        if let Err(e) = storage::save(HIGH_SCORES_KEY, &data) {
+            error!("failed to save high scores: {e}");
+        }
+    }
+
+    pub fn qualifies(&self, points: u32) -> bool {
+        if points == 0 {
+            return false;
+        }
+        self.entries.len() < MAX_HIGH_SCORES
+            || self.entries.last().is_some_and(|last| points > last.points)
+    }
+
+    pub fn insert(&mut self, entry: HighScoreEntry) {
+        let index = self
+            .entries
+            .iter()
+            .position(|e| entry.points > e.points)
+            .unwrap_or(self.entries.len());
+        self.entries.insert(index, entry);
+        self.entries.truncate(MAX_HIGH_SCORES);
+    }
+}
+
+/// Result of the last round, waiting for the player to type their initials
+#[derive(Resource)]
+pub struct PendingHighScore(pub HighScoreEntry);
+
+#[derive(Component)]
+pub struct NameEntryText {}
+
+pub fn title_show_high_scores(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    high_scores: Res<HighScores>,
+) {
+    if high_scores.entries.is_empty() {
+        return;
+    }
+
+    let mut table = String::from("high scores\n");
+    for (rank, entry) in high_scores.entries.iter().enumerate() {
+        table.push_str(&format!(
+            "{:>2}. {:<3} {:>6} {:>4} candy x{:.1} {:>2}:{:02} {}\n",
+            rank + 1,
+            entry.name,
+            entry.points,
+            entry.candies_eaten,
+            entry.final_scale,
+            entry.duration_seconds as u32 / 60,
+            entry.duration_seconds as u32 % 60,
+            entry.date,
+        ));
+    }
+
+    commands.spawn((
+        TextBundle::from_section(
+            table,
+            TextStyle {
+                font: asset_server.load("fonts/MesloLGS NF Regular.ttf"),
+                font_size: 20.0,
+                color: Color::WHITE,
+            },
+        )
+        .with_text_alignment(TextAlignment::Left)
+        .with_style(Style {
+            position_type: PositionType::Absolute,
+            top: Val::Px(5.0),
+            left: Val::Px(15.0),
+            ..default()
+        }),
+        Text {},
+    ));
+}
+
+pub fn name_entry_setup(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    pending: Res<PendingHighScore>,
+) {
+    info!("name_entry_setup");
+
+    commands.spawn((
+        TextBundle::from_sections([
+            TextSection::new(
+                format!(
+                    "new high score: {}!\ntype your initials, return to confirm\n",
+                    pending.0.points
+                ),
+                TextStyle {
+                    font: asset_server.load("fonts/MesloLGS NF Regular.ttf"),
+                    font_size: 30.0,
+                    color: Color::WHITE,
+                },
+            ),
+            TextSection::new(
+                "___",
+                TextStyle {
+                    font: asset_server.load("fonts/MesloLGS NF Regular.ttf"),
+                    font_size: 60.0,
+                    color: Color::YELLOW,
+                },
+            ),
+        ])
+        .with_text_alignment(TextAlignment::Left)
+        .with_style(Style {
+            position_type: PositionType::Absolute,
+            top: Val::Px(5.0),
+            left: Val::Px(15.0),
+            ..default()
+        }),
+        NameEntryText {},
+        Text {},
+    ));
+}
+
+pub fn name_entry_input(
+    mut characters: EventReader<ReceivedCharacter>,
+    keyboard_input: Res<Input<KeyCode>>,
+    actions: Actions,
+    mouse_buttons: Res<Input<MouseButton>>,
+    touches: Res<Touches>,
+    mut pending: ResMut<PendingHighScore>,
+    mut high_scores: ResMut<HighScores>,
+    mut text_query: Query<&mut bevy::text::Text, With<NameEntryText>>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    let name = &mut pending.0.name;
+
+    for event in characters.iter() {
+        if event.char.is_ascii_alphanumeric() && name.len() < NAME_LENGTH {
+            name.push(event.char.to_ascii_uppercase());
+        }
+    }
+
+    if keyboard_input.just_pressed(KeyCode::Back) {
+        name.pop();
+    }
+
+    if let Ok(mut text) = text_query.get_single_mut() {
+        text.sections[1].value = format!("{name:_<NAME_LENGTH$}");
+    }
+
+    // Return always confirms, like in any other text field
+    if keyboard_input.just_pressed(KeyCode::Return)
+        || actions.just_pressed(Action::Start)
+        || input::pointer_just_pressed(&touches, &mouse_buttons)
+    {
+        if name.is_empty() {
+            *name = DEFAULT_NAME.to_string();
+        }
+        info!("new high score: {:?}", pending.0);
+        high_scores.insert(pending.0.clone());
+        high_scores.save();
+        next_state.set(GameState::Title);
+    }
+}
+
+pub fn name_entry_teardown(mut commands: Commands, entities: Query<Entity, With<Text>>) {
+    info!("name_entry_teardown");
+
+    for entity in &entities {
+        commands.entity(entity).despawn();
+    }
+
+    commands.remove_resource::<PendingHighScore>();
+}
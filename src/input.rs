@@ -0,0 +1,317 @@
+use bevy::input::gamepad::{GamepadConnection, GamepadConnectionEvent};
+use bevy::prelude::*;
+use bevy::window::PrimaryWindow;
+
+use crate::controls::{Action, Actions};
+
+/// Stick deflection below this is ignored, above it the speed scales up from zero
+const GAMEPAD_DEAD_ZONE: f32 = 0.2;
+/// How far a finger or the mouse has to be dragged from where it went down
+/// for the caticorn to move at full speed
+const DRAG_FULL_SPEED_DISTANCE: f32 = 80.0;
+/// Number of caticorns that can share the arena
+pub const MAX_PLAYERS: usize = 2;
+
+/// Everything the simulation reads from the players in one frame. Gameplay
+/// systems only look at this, never at the devices, so that rounds can be
+/// recorded and replayed.
+#[derive(Resource, Default, Clone, Copy, PartialEq, Debug)]
+pub struct GameInput {
+    /// Steering of each player's caticorn, by player index
+    pub directions: [Vec2; MAX_PLAYERS],
+    /// Abandons the round, from the pause menu
+    pub quit: bool,
+    /// Starts the level over, from the pause menu
+    pub restart: bool,
+    pub force_end: bool,
+    pub debug_spawn: bool,
+    pub debug_grow: bool,
+}
+
+const QUIT: u8 = 1;
+const FORCE_END: u8 = 1 << 1;
+const DEBUG_SPAWN: u8 = 1 << 2;
+const DEBUG_GROW: u8 = 1 << 3;
+const RESTART: u8 = 1 << 4;
+
+impl GameInput {
+    /// Direction axes are stored with 8 bit precision, so the game only ever
+    /// sees values that survive a round trip through a replay file
+    pub fn quantized(mut self) -> Self {
+        for direction in &mut self.directions {
+            *direction = Vec2::new(
+                Self::quantize_axis(direction.x) as f32 / 127.0,
+                Self::quantize_axis(direction.y) as f32 / 127.0,
+            );
+        }
+        self
+    }
+
+    fn quantize_axis(value: f32) -> i8 {
+        (value.clamp(-1.0, 1.0) * 127.0).round() as i8
+    }
+
+    pub fn to_bytes(self) -> [u8; 5] {
+        let mut flags = 0;
+        if self.quit {
+            flags |= QUIT;
+        }
+        if self.force_end {
+            flags |= FORCE_END;
+        }
+        if self.debug_spawn {
+            flags |= DEBUG_SPAWN;
+        }
+        if self.debug_grow {
+            flags |= DEBUG_GROW;
+        }
+        if self.restart {
+            flags |= RESTART;
+        }
+        let [first, second] = self.directions;
+        [
+            Self::quantize_axis(first.x) as u8,
+            Self::quantize_axis(first.y) as u8,
+            Self::quantize_axis(second.x) as u8,
+            Self::quantize_axis(second.y) as u8,
+            flags,
+        ]
+    }
+
+    pub fn from_bytes(bytes: [u8; 5]) -> Self {
+        let axis = |byte: u8| byte as i8 as f32 / 127.0;
+        let flags = bytes[4];
+        GameInput {
+            directions: [
+                Vec2::new(axis(bytes[0]), axis(bytes[1])),
+                Vec2::new(axis(bytes[2]), axis(bytes[3])),
+            ],
+            quit: flags & QUIT != 0,
+            restart: flags & RESTART != 0,
+            force_end: flags & FORCE_END != 0,
+            debug_spawn: flags & DEBUG_SPAWN != 0,
+            debug_grow: flags & DEBUG_GROW != 0,
+        }
+    }
+}
+
+/// The gamepad steering each player's caticorn, by player index. Gamepads
+/// are handed out in the order they are connected, and a free one takes over
+/// when a player's gamepad goes away.
+#[derive(Resource, Default)]
+pub struct PlayerGamepads(pub [Option<Gamepad>; MAX_PLAYERS]);
+
+impl PlayerGamepads {
+    fn assign(&mut self, gamepad: Gamepad) {
+        if self.0.contains(&Some(gamepad)) {
+            return;
+        }
+        if let Some(slot) = self.0.iter_mut().find(|slot| slot.is_none()) {
+            *slot = Some(gamepad);
+        }
+    }
+}
+
+pub fn track_gamepads(
+    mut events: EventReader<GamepadConnectionEvent>,
+    gamepads: Res<Gamepads>,
+    mut player_gamepads: ResMut<PlayerGamepads>,
+) {
+    for event in events.iter() {
+        match &event.connection {
+            GamepadConnection::Connected(info) => {
+                info!("gamepad {} connected: {}", event.gamepad.id, info.name);
+                player_gamepads.assign(event.gamepad);
+            }
+            GamepadConnection::Disconnected => {
+                info!("gamepad {} disconnected", event.gamepad.id);
+                for slot in &mut player_gamepads.0 {
+                    if *slot == Some(event.gamepad) {
+                        *slot = None;
+                    }
+                }
+                for gamepad in gamepads.iter().filter(|gamepad| *gamepad != event.gamepad) {
+                    player_gamepads.assign(gamepad);
+                }
+            }
+        }
+    }
+}
+
+fn apply_dead_zone(stick: Vec2) -> Vec2 {
+    let length = stick.length();
+    if length < GAMEPAD_DEAD_ZONE {
+        return Vec2::ZERO;
+    }
+    let scaled = ((length - GAMEPAD_DEAD_ZONE) / (1.0 - GAMEPAD_DEAD_ZONE)).min(1.0);
+    stick * (scaled / length)
+}
+
+/// Virtual joystick: dragging a finger, or the mouse with the left button
+/// held, steers the caticorn relative to where the drag started
+#[derive(Resource, Default)]
+pub struct DragSteering {
+    mouse_start: Option<Vec2>,
+    pub direction: Vec2,
+}
+
+fn drag_direction(start: Vec2, position: Vec2) -> Vec2 {
+    let offset = position - start;
+    // window coordinates grow downwards, the arena upwards
+    (Vec2::new(offset.x, -offset.y) / DRAG_FULL_SPEED_DISTANCE).clamp_length_max(1.0)
+}
+
+pub fn track_drag(
+    touches: Res<Touches>,
+    mouse_buttons: Res<Input<MouseButton>>,
+    window_query: Query<&Window, With<PrimaryWindow>>,
+    mut drag: ResMut<DragSteering>,
+) {
+    let cursor_position = window_query
+        .get_single()
+        .ok()
+        .and_then(|window| window.cursor_position());
+
+    if mouse_buttons.just_pressed(MouseButton::Left) {
+        drag.mouse_start = cursor_position;
+    }
+    if !mouse_buttons.pressed(MouseButton::Left) {
+        drag.mouse_start = None;
+    }
+
+    // Browsers also emulate mouse events for touches, so a finger wins over
+    // the mouse instead of adding up with it
+    drag.direction = if let Some(touch) = touches.iter().next() {
+        drag_direction(touch.start_position(), touch.position())
+    } else if let (Some(start), Some(position)) = (drag.mouse_start, cursor_position) {
+        drag_direction(start, position)
+    } else {
+        Vec2::ZERO
+    };
+}
+
+/// A tap or a click, for starting and confirming things where there is no
+/// keyboard
+pub fn pointer_just_pressed(touches: &Touches, mouse_buttons: &Input<MouseButton>) -> bool {
+    touches.any_just_pressed() || mouse_buttons.just_pressed(MouseButton::Left)
+}
+
+pub fn read_input(
+    actions: Actions,
+    gamepad_axes: Res<Axis<GamepadAxis>>,
+    player_gamepads: Res<PlayerGamepads>,
+    drag: Res<DragSteering>,
+    mut input: ResMut<GameInput>,
+) {
+    let mut directions = [Vec2::ZERO; MAX_PLAYERS];
+
+    for (player, direction) in directions.iter_mut().enumerate() {
+        let [up, down, left, right] = Action::MOVEMENT[player];
+
+        if actions.pressed(left) {
+            *direction += Vec2::new(-1.0, 0.0);
+        }
+        if actions.pressed(right) {
+            *direction += Vec2::new(1.0, 0.0);
+        }
+        if actions.pressed(up) {
+            *direction += Vec2::new(0.0, 1.0);
+        }
+        if actions.pressed(down) {
+            *direction += Vec2::new(0.0, -1.0);
+        }
+
+        if let Some(gamepad) = player_gamepads.0[player] {
+            let axis = |axis_type| {
+                gamepad_axes
+                    .get(GamepadAxis::new(gamepad, axis_type))
+                    .unwrap_or(0.0)
+            };
+            *direction += apply_dead_zone(Vec2::new(
+                axis(GamepadAxisType::LeftStickX),
+                axis(GamepadAxisType::LeftStickY),
+            ));
+        }
+    }
+
+    // There is only one screen to drag on, it belongs to the first player
+    directions[0] += drag.direction;
+
+    *input = GameInput {
+        directions,
+        quit: input.quit,
+        restart: input.restart,
+        force_end: actions.pressed(Action::ForceEnd),
+        debug_spawn: input.debug_spawn || actions.just_pressed(Action::DebugSpawn),
+        debug_grow: actions.pressed(Action::DebugGrow),
+    }
+    .quantized();
+}
+
+/// Presses are collected every frame but must be acted on exactly once, by
+/// the next fixed update
+pub fn consume_one_shot_input(mut input: ResMut<GameInput>) {
+    input.quit = false;
+    input.restart = false;
+    input.debug_spawn = false;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bytes_round_trip() {
+        let input = GameInput {
+            directions: [Vec2::new(1.0, -1.0), Vec2::new(-0.3, 0.55)],
+            quit: true,
+            restart: false,
+            force_end: true,
+            debug_spawn: false,
+            debug_grow: true,
+        }
+        .quantized();
+        assert_eq!(GameInput::from_bytes(input.to_bytes()), input);
+    }
+
+    #[test]
+    fn every_flag_has_its_own_bit() {
+        let flags = [
+            GameInput {
+                quit: true,
+                ..default()
+            },
+            GameInput {
+                restart: true,
+                ..default()
+            },
+            GameInput {
+                force_end: true,
+                ..default()
+            },
+            GameInput {
+                debug_spawn: true,
+                ..default()
+            },
+            GameInput {
+                debug_grow: true,
+                ..default()
+            },
+        ];
+        for input in flags {
+            assert_eq!(input.to_bytes()[4].count_ones(), 1);
+            assert_eq!(GameInput::from_bytes(input.to_bytes()), input);
+        }
+    }
+
+    #[test]
+    fn quantized_input_survives_quantizing() {
+        let input = GameInput {
+            directions: [Vec2::new(0.123, -0.987), Vec2::new(2.0, -5.0)],
+            ..default()
+        }
+        .quantized();
+        assert_eq!(input.quantized(), input);
+        assert_eq!(input.directions[1], Vec2::new(1.0, -1.0));
+    }
+}
@@ -0,0 +1,245 @@
+//! The sequence of levels a run goes through, loaded from
+//! `assets/levels/campaign.levels.ron`. Until it has loaded, or if it can't
+//! be, the game has a single built-in level.
+//!
+//! Like the config, a round keeps the level it started with: changes to the
+//! file take effect from the next round on.
+
+use std::collections::BTreeMap;
+
+use bevy::asset::{AssetLoader, LoadContext, LoadState, LoadedAsset};
+use bevy::prelude::*;
+use bevy::reflect::{TypePath, TypeUuid};
+use bevy::utils::BoxedFuture;
+use serde::{Deserialize, Serialize};
+
+use crate::candy::CandyKind;
+use crate::config;
+use crate::network::NetworkSession;
+use crate::obstacle::ObstacleDef;
+use crate::protocol;
+use crate::replay::ReplayPlayback;
+
+pub const LEVELS_ASSET: &str = "levels/campaign.levels.ron";
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Level {
+    pub name: String,
+    /// Seconds between two candies appearing
+    pub candy_spawn_seconds: f32,
+    pub candy_speed: f32,
+    pub initial_candies: usize,
+    /// No more candies appear while there are more than this many
+    pub max_candy: usize,
+    /// Candies to eat, by all caticorns together, to clear the level
+    pub target: u32,
+    pub time_limit_seconds: f32,
+    /// How likely each kind of candy is to be the next one spawned
+    #[serde(default = "default_candy_weights")]
+    pub candy_weights: BTreeMap<CandyKind, u32>,
+    #[serde(default)]
+    pub obstacles: Vec<ObstacleDef>,
+}
+
+/// The mix of candy in online rounds
+fn default_candy_weights() -> BTreeMap<CandyKind, u32> {
+    CandyKind::ALL
+        .into_iter()
+        .zip(protocol::CANDY_KIND_WEIGHTS)
+        .filter(|&(_, weight)| weight > 0)
+        .collect()
+}
+
+impl Default for Level {
+    fn default() -> Self {
+        Level {
+            name: "first bites".to_string(),
+            candy_spawn_seconds: 0.66,
+            candy_speed: 250.0,
+            initial_candies: protocol::INITIAL_CANDIES,
+            max_candy: protocol::MAX_CANDY,
+            target: 30,
+            time_limit_seconds: 90.0,
+            candy_weights: default_candy_weights(),
+            obstacles: Vec::new(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, TypeUuid, TypePath, Clone, Debug)]
+#[uuid = "5b0a3c8e-21f4-4c57-9d0e-8f6c2a41b7d3"]
+pub struct LevelSet {
+    pub levels: Vec<Level>,
+}
+
+impl LevelSet {
+    /// Reads the level set straight from the assets folder, for when there
+    /// is no asset server
+    pub fn read() -> Option<LevelSet> {
+        config::read_asset(LEVELS_ASSET)
+    }
+}
+
+#[derive(Default)]
+pub struct LevelSetLoader;
+
+impl AssetLoader for LevelSetLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), bevy::asset::Error>> {
+        Box::pin(async move {
+            let level_set = ron::de::from_bytes::<LevelSet>(bytes)?;
+            load_context.set_default_asset(LoadedAsset::new(level_set));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["levels.ron"]
+    }
+}
+
+#[derive(Resource)]
+pub struct Levels {
+    levels: Vec<Level>,
+    /// Reloaded during a round, takes over when the next one starts
+    next_level_set: Option<LevelSet>,
+    /// Index of the level being played, or about to be
+    pub current: usize,
+    /// The level of the round being played, as it was when the round started
+    playing: Level,
+    handle: Option<Handle<LevelSet>>,
+    loaded: bool,
+}
+
+impl Default for Levels {
+    fn default() -> Self {
+        Levels {
+            levels: vec![Level::default()],
+            next_level_set: None,
+            current: 0,
+            playing: Level::default(),
+            handle: None,
+            loaded: true,
+        }
+    }
+}
+
+impl Levels {
+    pub fn new(level_set: Option<LevelSet>) -> Self {
+        let mut levels = Levels::default();
+        if let Some(level_set) = level_set {
+            levels.replace(level_set);
+        }
+        levels
+    }
+
+    pub fn current(&self) -> &Level {
+        &self.playing
+    }
+
+    /// Starts the level at `index`, or the last one if there aren't that many
+    pub fn select(&mut self, index: usize) {
+        self.current = index.min(self.levels.len() - 1);
+    }
+
+    /// Moves on to the next level, returns false if this was the last one
+    pub fn advance(&mut self) -> bool {
+        if self.current + 1 < self.levels.len() {
+            self.current += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn replace(&mut self, level_set: LevelSet) {
+        if level_set.levels.is_empty() {
+            warn!("ignoring level set without levels");
+            return;
+        }
+        info!("loaded {} levels", level_set.levels.len());
+        self.levels = level_set.levels;
+        self.select(self.current);
+        self.playing = self.levels[self.current].clone();
+    }
+
+    /// Fixes the level for the round about to start, taking over the level
+    /// set if it was reloaded since the last one
+    fn start_round(&mut self) {
+        if let Some(level_set) = self.next_level_set.take() {
+            self.replace(level_set);
+        }
+        self.playing = self.levels[self.current].clone();
+    }
+}
+
+/// Run condition holding the game until the level set is ready
+pub fn levels_loaded(levels: Res<Levels>) -> bool {
+    levels.loaded
+}
+
+pub fn levels_setup(mut levels: ResMut<Levels>, asset_server: Res<AssetServer>) {
+    info!("levels_setup");
+
+    levels.handle = Some(asset_server.load(LEVELS_ASSET));
+    levels.loaded = false;
+}
+
+/// Takes the level set over from the asset server when it has loaded. When
+/// it's reloaded, the new one waits for the next round.
+pub fn levels_update(
+    mut levels: ResMut<Levels>,
+    mut events: EventReader<AssetEvent<LevelSet>>,
+    level_sets: Res<Assets<LevelSet>>,
+    asset_server: Res<AssetServer>,
+) {
+    let Some(handle) = levels.handle.clone() else {
+        return;
+    };
+
+    for event in events.iter() {
+        match event {
+            AssetEvent::Created { handle: changed } | AssetEvent::Modified { handle: changed }
+                if *changed == handle =>
+            {
+                if let Some(level_set) = level_sets.get(&handle) {
+                    if levels.loaded {
+                        levels.next_level_set = Some(level_set.clone());
+                    } else {
+                        levels.replace(level_set.clone());
+                        levels.loaded = true;
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    if !levels.loaded && asset_server.get_load_state(&handle) == LoadState::Failed {
+        warn!("failed to load {LEVELS_ASSET}, playing the built-in level");
+        levels.loaded = true;
+    }
+}
+
+/// Fixes the level for the round about to start. Replays play the level they
+/// were recorded with, whatever the level set says now, and online rounds the
+/// built-in one, which the relay server hands out candy for.
+pub fn levels_start_round(
+    mut levels: ResMut<Levels>,
+    playback: Option<Res<ReplayPlayback>>,
+    online: Option<Res<NetworkSession>>,
+) {
+    info!("levels_start_round");
+
+    levels.start_round();
+    if let Some(playback) = playback {
+        levels.current = playback.level_index();
+        levels.playing = playback.level().clone();
+    } else if online.is_some() {
+        levels.current = 0;
+        levels.playing = Level::default();
+    }
+}
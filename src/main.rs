@@ -1,10 +1,56 @@
 #![allow(clippy::type_complexity)]
 #![allow(clippy::too_many_arguments)]
 
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use bevy::audio::AddAudioSource;
+use bevy::ecs::system::EntityCommands;
 use bevy::log::LogPlugin;
 use bevy::prelude::*;
-use bevy::window::{PresentMode, PrimaryWindow, WindowTheme};
+use bevy::transform::TransformSystem;
+use bevy::window::{PrimaryWindow, WindowTheme};
+use candy::{CandyBehavior, CandyKind};
 use clap::Parser;
+use collision::{Collider, SpatialIndex};
+use config::{GameConfig, GameConfigLoader, NextGameConfig};
+use controls::{Action, ActionMap, Actions};
+use highscore::{HighScoreEntry, HighScores, PendingHighScore};
+use input::{DragSteering, GameInput, PlayerGamepads, MAX_PLAYERS};
+use levels::{LevelSet, LevelSetLoader, Levels};
+use mixer::Mixer;
+use pause::PauseState;
+use powerup::{PowerUpFont, PowerUpKind, PowerUpSpawnTimer, PowerUpText, PowerUps};
+use rand::Rng;
+use replay::{Replay, ReplayPlayback};
+use rng::GameRng;
+use settings::{Settings, SettingsMenu, SettingsReturn};
+use tempo::{BeatClock, TempoMap, TempoMapLoader};
+use timestep::{Interpolated, SimulationTime, FIXED_TIMESTEP_SECONDS};
+
+mod candy;
+mod collision;
+mod config;
+mod controls;
+mod headless;
+mod highscore;
+mod input;
+mod levels;
+mod mixer;
+mod network;
+mod obstacle;
+mod pause;
+mod powerup;
+// Shared with the relay server, which uses more of it than the game does
+#[allow(dead_code)]
+mod protocol;
+mod replay;
+mod rng;
+mod settings;
+mod storage;
+mod tempo;
+mod timestep;
 
 pub mod built {
     include!(concat!(env!("OUT_DIR"), "/built.rs"));
@@ -16,36 +62,145 @@ struct Cli {
     /// Turn on debug logs (specify multiple time for more verbose logs)
     #[arg(short = 'v', long, action = clap::ArgAction::Count, global = true)]
     verbose: u8,
+
+    /// Seed of the first round, to reproduce a round exactly
+    #[arg(long)]
+    seed: Option<u64>,
+
+    /// Play back a recorded round (the last round is always saved as last_round.replay)
+    #[arg(long, value_name = "FILE")]
+    replay: Option<PathBuf>,
+
+    /// Simulate rounds without window, audio or renderer, with a bot steering the caticorn
+    #[arg(long)]
+    headless: bool,
+
+    /// Number of rounds to simulate in headless mode
+    #[arg(long, default_value_t = 1000)]
+    rounds: usize,
+
+    /// Simulated seconds after which a headless round is abandoned
+    #[arg(long, default_value_t = 300.0)]
+    max_round_seconds: f32,
+
+    /// Simulate a level with this many candies and print how long each step takes
+    #[arg(long, value_name = "CANDIES")]
+    bench: Option<usize>,
+
+    /// Number of fixed updates to simulate in benchmark mode
+    #[arg(long, default_value_t = 600)]
+    bench_steps: usize,
+
+    /// Play online against another caticorn through a relay server (cargo run --bin relay)
+    #[arg(long, value_name = "URL")]
+    connect: Option<String>,
 }
 
-const PLAYER_SPEED: f32 = 600.0;
-const CANDY_SPEED: f32 = 250.0;
-const CANDY_SPAWN_TIMER_SECONDS: f32 = 0.66;
-const NUMBER_OF_INITIAL_CANDIES: usize = 3;
-const MAX_CANDY: usize = 100;
+const POINTS_PER_CANDY: u32 = 10;
+const COMBO_WINDOW_SECONDS: f32 = 1.0;
+const MAX_COMBO_MULTIPLIER: u32 = 5;
+const GAME_OVER_SECONDS: f32 = 3.0;
+// The countdown turns red when the level is about to run out of time
+const COUNTDOWN_WARNING_SECONDS: f32 = 10.0;
+const ARENA_WIDTH: f32 = 800.0;
+const ARENA_HEIGHT: f32 = 600.0;
+// Sizes of sprites/caticorn.png and sprites/donut.png
+const PLAYER_SIZE: Vec2 = Vec2::new(83.0, 73.0);
+const CANDY_SIZE: Vec2 = Vec2::new(50.0, 41.0);
+// Sprite tint of each player's caticorn, so they can be told apart
+const PLAYER_COLORS: [Color; MAX_PLAYERS] = [Color::WHITE, Color::rgb(0.6, 0.8, 1.0)];
 
 #[derive(States, Default, Debug, Hash, Eq, PartialEq, Clone)]
 pub enum GameState {
     #[default]
     Init,
     Title,
+    Settings,
     Playing,
+    /// Passed through to start the level over
+    Restarting,
     End,
     Poop,
+    GameOver,
+    NameEntry,
 }
 
 #[derive(Component)]
-pub struct Player {}
+pub struct Player {
+    pub index: usize,
+}
+
+#[derive(Component, Default)]
+pub struct PlayerControl {
+    pub direction: Vec2,
+    pub grow: bool,
+}
+
+/// Hands out candy serials, starting over each round
+#[derive(Resource, Default)]
+pub struct CandySerials {
+    next: u32,
+}
+
+impl CandySerials {
+    pub fn next(&mut self) -> u32 {
+        let serial = self.next;
+        self.next += 1;
+        serial
+    }
+}
+
+/// Unscaled size of the entity's sprite
+#[derive(Component, Deref)]
+pub struct BodySize(Vec2);
 
 #[derive(Component)]
 pub struct Candy {
+    /// Order the simulation spawned it in. Unlike its `Entity`, it's the same
+    /// in every run of the round, so whatever has to go through candy in a
+    /// fixed order goes by this.
+    pub serial: u32,
+    pub kind: CandyKind,
     pub direction: Vec2,
     pub timestamp_changed_direction: f32,
+    /// Seconds the candy has been moving around
+    pub age: f32,
+}
+
+impl Candy {
+    pub fn new(serial: u32, kind: CandyKind, direction: Vec2) -> Self {
+        Candy {
+            serial,
+            kind,
+            direction,
+            timestamp_changed_direction: 0.0,
+            age: 0.0,
+        }
+    }
+
+    /// Where the candy is going this step
+    pub fn heading(&self) -> Vec2 {
+        match self.kind.properties().behavior {
+            CandyBehavior::Zigzag => {
+                let leg = (self.age / candy::ZIGZAG_LEG_SECONDS) as u32;
+                let angle = if leg % 2 == 0 {
+                    candy::ZIGZAG_ANGLE
+                } else {
+                    -candy::ZIGZAG_ANGLE
+                };
+                Vec2::from_angle(angle).rotate(self.direction)
+            }
+            _ => self.direction,
+        }
+    }
 }
 
 #[derive(Component)]
 pub struct Text {}
 
+#[derive(Component)]
+pub struct ScoreText {}
+
 #[derive(Resource)]
 pub struct PreloadedResources {
     _resources: Vec<Handle<AudioSource>>,
@@ -57,17 +212,17 @@ pub struct CandyChangeDirectionSound {
 }
 
 impl CandyChangeDirectionSound {
-    pub fn select_random(&self) -> Handle<AudioSource> {
-        self.sounds[rand::random::<usize>() % self.sounds.len()].clone()
+    pub fn select_random(&self, rng: &mut impl Rng) -> Handle<AudioSource> {
+        self.sounds[rng.gen_range(0..self.sounds.len())].clone()
     }
 }
 
-#[derive(Resource)]
-pub struct Music(Option<Handle<AudioSink>>);
-
 #[derive(Resource, Deref)]
 pub struct PlayerCandyCollisionSound(Handle<AudioSource>);
 
+#[derive(Resource, Deref)]
+pub struct PowerUpSound(Handle<AudioSource>);
+
 #[derive(Resource, Deref)]
 pub struct PlayerImage(Handle<Image>);
 
@@ -77,17 +232,139 @@ pub struct CandyImage(Handle<Image>);
 #[derive(Resource, Deref, DerefMut)]
 pub struct CandySpawnTimer(Timer);
 
+/// Playfield dimensions, centered on the origin
 #[derive(Resource)]
-pub struct ShrinkData {
-    initial_scale_x: f32,
-    total_time: f32,
+pub struct Arena {
+    pub width: f32,
+    pub height: f32,
+}
+
+/// Sounds from the simulation, with where in the arena they happen
+#[derive(Event, Clone, Copy, Debug)]
+pub enum GameSound {
+    CandyBounce(Vec2),
+    PlayerEat(Vec2),
+    PowerUp,
+}
+
+/// How many caticorns take part in the round
+#[derive(Resource, Deref, DerefMut)]
+pub struct PlayerCount(pub usize);
+
+#[derive(Component, Default, Clone)]
+pub struct Score {
+    pub points: u32,
+    pub candies_eaten: u32,
+    pub combo: u32,
+    pub best_combo: u32,
+    last_eat_time: f32,
+}
+
+impl Score {
+    /// Eats within COMBO_WINDOW_SECONDS of each other build up the combo
+    pub fn register_eat(&mut self, now: f32, points: u32) {
+        if self.candies_eaten > 0 && now - self.last_eat_time <= COMBO_WINDOW_SECONDS {
+            self.combo += 1;
+        } else {
+            self.combo = 1;
+        }
+        self.best_combo = self.best_combo.max(self.combo);
+        self.candies_eaten += 1;
+        self.last_eat_time = now;
+        self.points += points * self.multiplier();
+    }
+
+    pub fn break_combo(&mut self) {
+        self.combo = 0;
+    }
+
+    pub fn multiplier(&self) -> u32 {
+        self.combo.clamp(1, MAX_COMBO_MULTIPLIER)
+    }
+
+    pub fn active_multiplier(&self, now: f32) -> u32 {
+        if now - self.last_eat_time <= COMBO_WINDOW_SECONDS {
+            self.multiplier()
+        } else {
+            1
+        }
+    }
+}
+
+/// Why a round was lost
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum GameOverReason {
+    TimeUp,
+    CandyOverflow,
+}
+
+impl GameOverReason {
+    pub fn describe(&self) -> &'static str {
+        match self {
+            GameOverReason::TimeUp => "time's up!",
+            GameOverReason::CandyOverflow => "the candy took over!",
+        }
+    }
+}
+
+#[derive(Resource, Default)]
+pub struct RoundStats {
+    pub seed: u64,
+    pub level: usize,
+    /// Candies the caticorns had eaten in earlier levels of the run
+    pub candies_at_start: u32,
+    /// Scores from earlier levels of the run, by player index, to go back to
+    /// when the level is restarted
+    pub scores_at_start: [Score; MAX_PLAYERS],
+    /// Seconds spent in earlier levels of the run
+    pub duration_at_start: f32,
+    /// How much the first caticorn grew in earlier levels of the run, before
+    /// being brought back to size for this one
+    pub growth_at_start: f32,
+    pub start_time: f32,
+    pub duration: f32,
+    pub final_scale: f32,
+    pub game_over: Option<GameOverReason>,
+}
+
+impl RoundStats {
+    /// Seconds the run has lasted, this level included
+    pub fn run_duration(&self) -> f32 {
+        self.duration_at_start + self.duration
+    }
+
+    /// The size the first caticorn would have ended the run at, had it kept
+    /// its growth from one level to the next
+    pub fn run_final_scale(&self) -> f32 {
+        self.final_scale + self.growth_at_start
+    }
+
+    /// Adds this level's time and growth to the run's, for the next level
+    /// to go on from
+    fn carry_over(&mut self) {
+        self.duration_at_start = self.run_duration();
+        self.growth_at_start = self.run_final_scale() - 1.0;
+    }
 }
 
 #[derive(Resource)]
-pub struct TitlePulseData {
-    start_time: f32,
+pub struct ShrinkData {
+    initial_scales: [f32; MAX_PLAYERS],
+    total_time: f32,
 }
 
+/// Everything that runs once per step of the simulation
+#[derive(SystemSet, Debug, Hash, PartialEq, Eq, Clone)]
+pub struct StepSet;
+
+/// Systems turning device input or replays into GameInput and PlayerControl
+#[derive(SystemSet, Debug, Hash, PartialEq, Eq, Clone)]
+pub struct InputSet;
+
+/// The gameplay simulation
+#[derive(SystemSet, Debug, Hash, PartialEq, Eq, Clone)]
+pub struct SimulationSet;
+
 #[derive(Debug)]
 struct Rect {
     min_x: f32,
@@ -101,6 +378,29 @@ fn main() {
 
     info!("args: {:?}", &args);
 
+    if args.headless {
+        headless::run(args.rounds, args.max_round_seconds, args.seed);
+        return;
+    }
+
+    if let Some(candies) = args.bench {
+        headless::bench(candies, args.bench_steps, args.seed);
+        return;
+    }
+
+    let replay = match args.replay.as_deref().map(Replay::load).transpose() {
+        Ok(replay) => replay,
+        Err(e) => {
+            eprintln!("failed to load replay: {e}");
+            std::process::exit(1);
+        }
+    };
+    let seed = replay
+        .as_ref()
+        .map_or(args.seed, |replay| Some(replay.seed));
+
+    let settings = Settings::load();
+
     let mut app = App::new();
 
     app.add_plugins(
@@ -110,13 +410,20 @@ fn main() {
                 level: bevy::log::Level::WARN,
             })
             .set(ImagePlugin::default_nearest())
+            .set(AssetPlugin {
+                // Picks up changes to the config and levels while the game runs
+                watch_for_changes: !cfg!(target_arch = "wasm32"),
+                ..default()
+            })
             .set(WindowPlugin {
                 primary_window: Some(Window {
                     title: "The Fat Caticorn".into(),
-                    resolution: (800., 600.).into(),
-                    present_mode: PresentMode::AutoVsync,
-                    // Tells wasm to resize the window according to the available canvas
-                    fit_canvas_to_parent: false,
+                    resolution: (settings.resolution.0 as f32, settings.resolution.1 as f32).into(),
+                    present_mode: settings.present_mode(),
+                    mode: settings.window_mode(),
+                    // Tells wasm to resize the window according to the available canvas,
+                    // so that it fills the viewport on phones
+                    fit_canvas_to_parent: true,
                     // Tells wasm not to override default event handling, like F5, Ctrl+R etc.
                     prevent_default_event_handling: false,
                     window_theme: Some(WindowTheme::Dark),
@@ -127,24 +434,96 @@ fn main() {
             }),
     )
     .insert_resource(ClearColor(Color::rgb(0.0, 0.0, 0.0)))
-    .insert_resource(CandySpawnTimer(Timer::from_seconds(
-        CANDY_SPAWN_TIMER_SECONDS,
-        TimerMode::Repeating,
-    )))
-    .insert_resource(Music(None))
-    .add_state::<GameState>()
-    .add_systems(Startup, setup)
+    .insert_resource(mixer::Channels::default())
+    .insert_resource(PlayerGamepads::default())
+    .insert_resource(DragSteering::default())
+    .insert_resource(ActionMap::load())
+    .insert_resource(settings)
+    .add_asset::<LevelSet>()
+    .init_asset_loader::<LevelSetLoader>()
+    .add_asset::<GameConfig>()
+    .init_asset_loader::<GameConfigLoader>()
+    .insert_resource(TempoMap::default())
+    .insert_resource(BeatClock::default())
+    .add_asset::<TempoMap>()
+    .init_asset_loader::<TempoMapLoader>()
+    .add_audio_source::<mixer::MusicSource>();
+
+    add_gameplay(&mut app, seed);
+
+    app.add_systems(
+        Startup,
+        (
+            setup,
+            levels::levels_setup,
+            config::config_setup,
+            tempo::tempo_setup,
+        ),
+    )
+    .add_systems(
+        PreUpdate,
+        (
+            update_arena
+                .run_if(network::offline)
+                .run_if(replay::not_playing_back),
+            input::track_gamepads,
+            levels::levels_update,
+            config::config_update,
+            tempo::tempo_update,
+        ),
+    )
+    .add_systems(
+        FixedUpdate,
+        (
+            timestep::restore_simulated_positions.before(SimulationSet),
+            timestep::store_simulated_positions.after(SimulationSet),
+        )
+            .run_if(in_state(GameState::Playing).or_else(in_state(GameState::End))),
+    )
+    .add_systems(
+        PostUpdate,
+        timestep::interpolate_positions
+            .before(TransformSystem::TransformPropagate)
+            .run_if(in_state(GameState::Playing).or_else(in_state(GameState::End))),
+    )
+    .add_systems(
+        OnExit(GameState::End),
+        timestep::snap_to_simulated_positions,
+    )
+    .add_systems(
+        Update,
+        (
+            obstacle::obstacle_presentation,
+            mixer::mixer_update,
+            tempo::beat_clock_update.after(mixer::mixer_update),
+        ),
+    )
     .add_systems(OnEnter(GameState::Init), init_setup)
     .add_systems(OnExit(GameState::Init), init_teardown)
-    .add_systems(OnEnter(GameState::Title), title_setup)
+    .add_systems(
+        OnEnter(GameState::Title),
+        (title_setup, highscore::title_show_high_scores),
+    )
     .add_systems(OnExit(GameState::Title), title_teardown)
-    .add_systems(OnEnter(GameState::Playing), gameplay_setup)
-    .add_systems(OnExit(GameState::Playing), gameplay_teardown)
+    .add_systems(OnEnter(GameState::Settings), settings::settings_setup)
+    .add_systems(OnExit(GameState::Settings), settings::settings_teardown)
+    .add_systems(OnEnter(GameState::Playing), gameplay_presentation_setup)
+    .add_systems(OnExit(GameState::Playing), gameplay_stop_music)
+    .add_systems(OnEnter(GameState::End), end_setup)
     .add_systems(OnEnter(GameState::Poop), poop_setup)
     .add_systems(OnExit(GameState::Poop), poop_teardown)
+    .add_systems(
+        OnEnter(GameState::GameOver),
+        (timestep::snap_to_simulated_positions, game_over_setup).chain(),
+    )
+    .add_systems(OnExit(GameState::GameOver), game_over_teardown)
+    .add_systems(OnEnter(GameState::NameEntry), highscore::name_entry_setup)
+    .add_systems(OnExit(GameState::NameEntry), highscore::name_entry_teardown)
     .add_systems(
         Update,
-        (init_wait_for_input,).run_if(in_state(GameState::Init)),
+        (init_wait_for_input,)
+            .run_if(in_state(GameState::Init))
+            .run_if(levels::levels_loaded),
     )
     .add_systems(
         Update,
@@ -152,39 +531,251 @@ fn main() {
     )
     .add_systems(
         Update,
+        settings::settings_input
+            .run_if(in_state(GameState::Settings).or_else(in_state(PauseState::Settings))),
+    )
+    .add_systems(
+        FixedUpdate,
+        gameplay_apply_input
+            .in_set(InputSet)
+            .run_if(in_state(GameState::Playing)),
+    )
+    .add_systems(
+        Update,
+        (
+            gameplay_update_score_text,
+            powerup::gameplay_update_power_up_text,
+            play_game_sounds,
+        )
+            .run_if(in_state(GameState::Playing)),
+    )
+    .add_systems(
+        FixedUpdate,
+        (end_sequence,)
+            .in_set(SimulationSet)
+            .run_if(in_state(GameState::End)),
+    )
+    .add_systems(Update, (poop_sequence,).run_if(in_state(GameState::Poop)))
+    .add_systems(
+        Update,
+        (game_over_sequence,).run_if(in_state(GameState::GameOver)),
+    )
+    .add_systems(
+        Update,
+        (highscore::name_entry_input,).run_if(in_state(GameState::NameEntry)),
+    );
+
+    if let Some(replay) = replay {
+        let playback = ReplayPlayback::new(replay);
+        app.insert_resource(playback)
+            .add_systems(
+                Update,
+                replay::replay_start
+                    .after(init_wait_for_input)
+                    .run_if(in_state(GameState::Init))
+                    .run_if(levels::levels_loaded),
+            )
+            .add_systems(
+                FixedUpdate,
+                replay::replay_playback
+                    .in_set(InputSet)
+                    .before(gameplay_apply_input)
+                    .run_if(in_state(GameState::Playing)),
+            )
+            .add_systems(OnEnter(GameState::Title), replay::replay_finished);
+    } else {
+        app.add_systems(
+            PreUpdate,
+            (input::track_drag, input::read_input)
+                .chain()
+                .after(bevy::input::InputSystem)
+                .after(input::track_gamepads),
+        )
+        .add_systems(
+            Update,
+            pause::gameplay_pause
+                .run_if(in_state(GameState::Playing))
+                .run_if(in_state(PauseState::Running)),
+        )
+        .add_systems(OnEnter(PauseState::Paused), pause::pause_setup)
+        .add_systems(OnExit(PauseState::Paused), pause::pause_teardown)
+        .add_systems(OnEnter(PauseState::Settings), settings::settings_setup)
+        .add_systems(OnExit(PauseState::Settings), settings::settings_teardown)
+        .add_systems(
+            Update,
+            pause::pause_menu_input.run_if(in_state(PauseState::Paused)),
+        );
+        if let Some(url) = &args.connect {
+            app.insert_non_send_resource(network::Connection::open(url))
+                .configure_set(
+                    FixedUpdate,
+                    StepSet.run_if(not(in_state(GameState::Playing)).or_else(network::frame_ready)),
+                )
+                .add_systems(OnEnter(GameState::Title), network::net_join)
+                .add_systems(OnExit(GameState::Title), network::net_leave_queue)
+                .add_systems(
+                    PreUpdate,
+                    (
+                        network::net_receive,
+                        network::net_send_input
+                            .after(network::net_receive)
+                            .after(input::read_input)
+                            .run_if(in_state(GameState::Playing)),
+                    ),
+                )
+                .add_systems(
+                    FixedUpdate,
+                    (
+                        network::net_apply_frame
+                            .in_set(InputSet)
+                            .before(gameplay_apply_input),
+                        network::net_claim_candy
+                            .in_set(SimulationSet)
+                            .after(gameplay_player_movement)
+                            .after(gameplay_candy_collision),
+                    )
+                        .run_if(in_state(GameState::Playing)),
+                );
+        }
+        if !cfg!(target_arch = "wasm32") {
+            // Online rounds depend on the relay server and can't be replayed
+            app.add_systems(
+                OnEnter(GameState::Playing),
+                replay::replay_start_recording
+                    .after(gameplay_setup)
+                    .run_if(network::offline),
+            )
+            .add_systems(
+                FixedUpdate,
+                // Like the simulation, recording holds still while paused
+                replay::replay_record_frame
+                    .in_set(StepSet)
+                    .after(InputSet)
+                    .before(input::consume_one_shot_input)
+                    .run_if(in_state(GameState::Playing)),
+            )
+            .add_systems(OnExit(GameState::Playing), replay::replay_save_recording);
+        }
+    }
+
+    // app.add_plugins(bevy::diagnostic::LogDiagnosticsPlugin::default());
+    // app.add_plugins(bevy::diagnostic::FrameTimeDiagnosticsPlugin::default());
+
+    app.run();
+}
+
+/// State, resources and systems of the simulation itself, shared by the
+/// windowed game and the headless mode.
+fn add_gameplay(app: &mut App, seed: Option<u64>) {
+    let levels = Levels::default();
+    app.insert_resource(CandySpawnTimer(Timer::from_seconds(
+        levels.current().candy_spawn_seconds,
+        TimerMode::Repeating,
+    )))
+    .insert_resource(levels)
+    .insert_resource(SpatialIndex::default())
+    .insert_resource(CandySerials::default())
+    .insert_resource(GameConfig::default())
+    .insert_resource(NextGameConfig::default())
+    .insert_resource(PowerUpSpawnTimer(Timer::from_seconds(
+        powerup::POWER_UP_SPAWN_SECONDS,
+        TimerMode::Repeating,
+    )))
+    .insert_resource(Arena {
+        width: ARENA_WIDTH,
+        height: ARENA_HEIGHT,
+    })
+    .insert_resource(PlayerCount(1))
+    .insert_resource(RoundStats::default())
+    .insert_resource(GameRng::new(seed))
+    .insert_resource(GameInput::default())
+    .insert_resource(FixedTime::new_from_secs(FIXED_TIMESTEP_SECONDS))
+    .insert_resource(SimulationTime::default())
+    .add_event::<GameSound>()
+    .add_state::<GameState>()
+    .add_state::<PauseState>()
+    .configure_set(FixedUpdate, InputSet.in_set(StepSet).before(SimulationSet))
+    .configure_set(FixedUpdate, StepSet.run_if(in_state(PauseState::Running)))
+    .configure_set(
+        FixedUpdate,
+        SimulationSet
+            .in_set(StepSet)
+            .run_if(timestep::no_state_change_pending),
+    )
+    .add_systems(
+        FixedUpdate,
+        (
+            timestep::advance_simulation_time.before(InputSet),
+            // Candy spawned from input, like the relay server's, has to be
+            // there by the time the simulation counts it
+            apply_deferred.after(InputSet).before(SimulationSet),
+            input::consume_one_shot_input.after(SimulationSet),
+        )
+            .in_set(StepSet),
+    )
+    .add_systems(
+        OnEnter(GameState::Playing),
+        (
+            config::config_start_round.before(gameplay_setup),
+            levels::levels_start_round
+                .before(gameplay_setup)
+                .before(obstacle::gameplay_spawn_obstacles),
+            gameplay_setup,
+            gameplay_spawn_initial_candy
+                .after(gameplay_setup)
+                .run_if(network::offline),
+            powerup::gameplay_power_up_setup,
+            obstacle::gameplay_spawn_obstacles,
+        ),
+    )
+    .add_systems(OnExit(GameState::Playing), gameplay_teardown)
+    .add_systems(OnEnter(GameState::Restarting), restart_setup)
+    .add_systems(
+        FixedUpdate,
         (
             gameplay_exit_to_title,
             gameplay_await_zero_candy,
+            gameplay_await_level_end,
             gameplay_player_movement,
             gameplay_candy_movement,
-            gameplay_spawn_candy_timer,
-            gameplay_update_candy_direction.after(gameplay_candy_movement),
+            gameplay_spawn_candy_timer.run_if(network::offline),
+            collision::gameplay_index_candy.after(gameplay_candy_movement),
+            gameplay_candy_collision.after(collision::gameplay_index_candy),
+            gameplay_update_candy_direction.after(gameplay_candy_collision),
             gameplay_player_candy_collision
                 .after(gameplay_player_movement)
-                .after(gameplay_candy_movement),
+                .after(gameplay_candy_collision)
+                .run_if(network::offline),
             gameplay_confine_entity_movement
                 .after(gameplay_player_candy_collision)
                 .after(gameplay_update_candy_direction),
+            // Power-ups are left out of online rounds, like the candy the
+            // relay server doesn't hand out
+            powerup::gameplay_spawn_power_up.run_if(network::offline),
+            powerup::gameplay_power_up_pickup
+                .after(gameplay_player_movement)
+                .before(gameplay_confine_entity_movement),
+            powerup::gameplay_tick_power_ups,
+            obstacle::gameplay_spin_obstacles,
+            obstacle::gameplay_obstacle_collision
+                .after(obstacle::gameplay_spin_obstacles)
+                .after(gameplay_player_candy_collision)
+                .after(gameplay_update_candy_direction)
+                .before(gameplay_confine_entity_movement),
         )
+            .in_set(SimulationSet)
             .run_if(in_state(GameState::Playing)),
-    )
-    .add_systems(Update, (end_sequence,).run_if(in_state(GameState::End)))
-    .add_systems(Update, (poop_sequence,).run_if(in_state(GameState::Poop)));
-
-    // app.add_plugins(bevy::diagnostic::LogDiagnosticsPlugin::default());
-    // app.add_plugins(bevy::diagnostic::FrameTimeDiagnosticsPlugin::default());
-
-    app.run();
+    );
 }
 
-fn calculate_confinement_rect(window: &Window, image: &Image, transform: &Transform) -> Rect {
-    let half_size_x = (image.size().x * transform.scale.x) / 2.0;
-    let half_size_y = (image.size().y * transform.scale.y) / 2.0;
+fn calculate_confinement_rect(arena: &Arena, size: Vec2, transform: &Transform) -> Rect {
+    let half_size_x = (size.x * transform.scale.x) / 2.0;
+    let half_size_y = (size.y * transform.scale.y) / 2.0;
 
-    let min_x = -(window.width() / 2.0) + half_size_x;
-    let max_x = (window.width() / 2.0) - half_size_x;
-    let min_y = -(window.height() / 2.0) + half_size_y;
-    let max_y = (window.height() / 2.0) - half_size_y;
+    let min_x = -(arena.width / 2.0) + half_size_x;
+    let max_x = (arena.width / 2.0) - half_size_x;
+    let min_y = -(arena.height / 2.0) + half_size_y;
+    let max_y = (arena.height / 2.0) - half_size_y;
 
     Rect {
         min_x,
@@ -194,12 +785,75 @@ fn calculate_confinement_rect(window: &Window, image: &Image, transform: &Transf
     }
 }
 
-fn stop_music(mut music: ResMut<Music>, audio_sinks: Res<Assets<AudioSink>>) {
-    if music.0.is_some() {
-        if let Some(sink) = audio_sinks.get(music.0.as_ref().unwrap()) {
-            sink.stop();
-        }
-        music.0 = None;
+/// Candies eaten by all caticorns since the level started
+fn level_candies_eaten<'a>(
+    scores: impl Iterator<Item = &'a Score>,
+    round_stats: &RoundStats,
+) -> u32 {
+    scores
+        .map(|score| score.candies_eaten)
+        .sum::<u32>()
+        .saturating_sub(round_stats.candies_at_start)
+}
+
+fn feed_player(
+    player_transform: &mut Transform,
+    score: &mut Score,
+    kind: CandyKind,
+    now: f32,
+    config: &GameConfig,
+) {
+    let properties = kind.properties();
+    player_transform.scale.x += config.growth(kind);
+    player_transform.scale.y += config.growth(kind);
+    if properties.points > 0 {
+        score.register_eat(now, properties.points);
+    } else {
+        score.break_combo();
+    }
+}
+
+/// Where a caticorn starts the round, and waddles back to when it ends
+fn home_position(index: usize, player_count: usize, arena: &Arena) -> Vec3 {
+    let spacing = arena.width / player_count as f32;
+    Vec3::new(
+        -arena.width / 2.0 + spacing * (index as f32 + 0.5),
+        0.0,
+        0.0,
+    )
+}
+
+fn spawn_player(
+    commands: &mut Commands,
+    player_image: &PlayerImage,
+    index: usize,
+    translation: Vec3,
+    score: Score,
+) {
+    commands.spawn((
+        SpriteBundle {
+            transform: Transform::from_translation(translation),
+            texture: player_image.0.clone(),
+            sprite: Sprite {
+                color: PLAYER_COLORS[index],
+                ..default()
+            },
+            ..default()
+        },
+        Player { index },
+        PlayerControl::default(),
+        BodySize(PLAYER_SIZE),
+        Collider(collision::CATICORN_SHAPE),
+        Interpolated::new(translation),
+        score,
+        PowerUps::default(),
+    ));
+}
+
+pub fn update_arena(mut arena: ResMut<Arena>, window_query: Query<&Window, With<PrimaryWindow>>) {
+    if let Ok(window) = window_query.get_single() {
+        arena.width = window.width();
+        arena.height = window.height();
     }
 }
 
@@ -219,21 +873,29 @@ pub fn setup(mut commands: Commands, asset_server: Res<AssetServer>) {
         asset_server.load("audio/caticorn_eat_candy.ogg"),
     ));
 
+    commands.insert_resource(PowerUpSound(
+        asset_server.load("audio/player_candy_collision.ogg"),
+    ));
+
+    commands.insert_resource(PowerUpFont(
+        asset_server.load("fonts/MesloLGS NF Regular.ttf"),
+    ));
+
     let player_image = PlayerImage(asset_server.load("sprites/caticorn.png"));
 
     let candy_image = CandyImage(asset_server.load("sprites/donut.png"));
 
-    commands.spawn((
-        SpriteBundle {
-            transform: Transform::from_xyz(0.0, 0.0, 0.0),
-            texture: player_image.clone(),
-            ..default()
-        },
-        Player {},
-    ));
+    spawn_player(
+        &mut commands,
+        &player_image,
+        0,
+        Vec3::ZERO,
+        Score::default(),
+    );
 
     commands.insert_resource(player_image);
     commands.insert_resource(candy_image);
+    commands.insert_resource(HighScores::load());
 
     commands.insert_resource(PreloadedResources {
         _resources: vec![
@@ -251,7 +913,7 @@ pub fn init_setup(mut commands: Commands, asset_server: Res<AssetServer>) {
     commands.spawn((
         TextBundle::from_section(
             format!(
-                "mouse click to activate\n({} {})",
+                "click or tap to activate\n({} {})",
                 built::PKG_VERSION,
                 built::GIT_COMMIT_HASH_SHORT.unwrap_or("?"),
             ),
@@ -272,43 +934,55 @@ pub fn init_setup(mut commands: Commands, asset_server: Res<AssetServer>) {
     ));
 }
 
-pub fn init_teardown() {
+pub fn init_teardown(mut commands: Commands, entities: Query<Entity, With<Text>>) {
     info!("init_teardown");
+
+    for entity in &entities {
+        commands.entity(entity).despawn();
+    }
 }
 
 pub fn init_wait_for_input(
     buttons: Res<Input<MouseButton>>,
+    touches: Res<Touches>,
+    actions: Actions,
     mut next_state: ResMut<NextState<GameState>>,
 ) {
-    if buttons.just_pressed(MouseButton::Left) {
+    if input::pointer_just_pressed(&touches, &buttons) || actions.just_pressed(Action::Start) {
         next_state.set(GameState::Title)
     }
 }
 
 pub fn title_setup(
     mut commands: Commands,
-    mut player_query: Query<&mut Transform, With<Player>>,
+    mut player_query: Query<(Entity, &Player, &mut Transform)>,
     asset_server: Res<AssetServer>,
-    audio: Res<Audio>,
-    audio_sinks: Res<Assets<AudioSink>>,
-    mut music: ResMut<Music>,
+    mut mixer: Mixer,
     entities: Query<Entity, (Without<Camera>, Without<Window>, Without<Player>)>,
-    time: Res<Time>,
+    mut levels: ResMut<Levels>,
 ) {
     info!("title_setup");
 
+    // Every run starts from the first level
+    levels.select(0);
+
     for entity in &entities {
         commands.entity(entity).despawn();
     }
 
-    if let Ok(mut transform) = player_query.get_single_mut() {
-        transform.translation = Vec3::default();
-        transform.scale = Vec3::new(1.0, 1.0, 1.0);
+    // Only the first caticorn sticks around between rounds
+    for (entity, player, mut transform) in &mut player_query {
+        if player.index == 0 {
+            transform.translation = Vec3::default();
+            transform.scale = Vec3::new(1.0, 1.0, 1.0);
+        } else {
+            commands.entity(entity).despawn();
+        }
     }
 
     commands.spawn((
         TextBundle::from_section(
-            "press space to start",
+            "press space or tap to start\npress 2 for two players\npress tab for settings",
             TextStyle {
                 font: asset_server.load("fonts/MesloLGS NF Regular.ttf"),
                 font_size: 30.0,
@@ -325,44 +999,21 @@ pub fn title_setup(
         Text {},
     ));
 
-    let weak_handle = audio.play_with_settings(
-        asset_server.load("music/music_title.ogg"),
-        PlaybackSettings {
-            repeat: true,
-            volume: Default::default(),
-            speed: 1.0,
-        },
-    );
-
-    let strong_handle = audio_sinks.get_handle(weak_handle);
-
-    music.0 = Some(strong_handle);
-
-    commands.insert_resource(TitlePulseData {
-        start_time: time.elapsed_seconds(),
-    });
+    mixer.play_music(asset_server.load("music/music_title.ogg"));
 }
 
 pub fn title_player_pulse(
     mut player_query: Query<&mut Transform, With<Player>>,
-    time: Res<Time>,
-    pulse_data: Res<TitlePulseData>,
+    beat_clock: Res<BeatClock>,
 ) {
     //debug!("title_player_pulse");
 
     if let Ok(mut transform) = player_query.get_single_mut() {
-        let elapsed = time.elapsed_seconds() - pulse_data.start_time;
-        // 2.8 fast
-        // 2.75 slow
-        // 2.78 liiiitle fast
-        // 2.778 liiiiiiitle fast
-        // 2.777 liiiiiiitle fast
-        // 2.776 liiiiiiiiiiiitle fast
-        // 2.774 liiiiiiiiiiiiiiiitle fast
-        // 2.77 liiiiiiiite långsam
-        let music_speed_factor = 2.772;
+        // Grows and shrinks back once every two beats, in step with the
+        // music, whose tempo is in music/music.tempo.ron
+        let beat = beat_clock.beat().unwrap_or(0.0);
         let max_size = 1.5;
-        let size = 1.0 + (elapsed * music_speed_factor).sin().abs() * max_size;
+        let size = 1.0 + (beat * std::f32::consts::FRAC_PI_2).sin().abs() * max_size;
         transform.scale.x = size;
         transform.scale.y = size;
     }
@@ -371,8 +1022,7 @@ pub fn title_player_pulse(
 pub fn title_teardown(
     mut commands: Commands,
     entities: Query<Entity, With<Text>>,
-    music: ResMut<Music>,
-    audio_sinks: Res<Assets<AudioSink>>,
+    mut mixer: Mixer,
 ) {
     info!("title_teardown");
 
@@ -380,110 +1030,286 @@ pub fn title_teardown(
         commands.entity(entity).despawn();
     }
 
-    stop_music(music, audio_sinks);
+    mixer.stop_music();
 }
 
 pub fn title_wait_for_keypress(
-    keyboard_input: Res<Input<KeyCode>>,
+    mut commands: Commands,
+    actions: Actions,
+    buttons: Res<Input<MouseButton>>,
+    touches: Res<Touches>,
+    mut player_count: ResMut<PlayerCount>,
     mut next_state: ResMut<NextState<GameState>>,
 ) {
-    if keyboard_input.just_pressed(KeyCode::Space) {
+    if actions.just_pressed(Action::Start) || input::pointer_just_pressed(&touches, &buttons) {
+        **player_count = 1;
+        next_state.set(GameState::Playing)
+    } else if actions.just_pressed(Action::StartTwoPlayers) {
+        **player_count = 2;
         next_state.set(GameState::Playing)
+    } else if actions.just_pressed(Action::Settings) {
+        commands.insert_resource(SettingsMenu::new(SettingsReturn::Title));
+        next_state.set(GameState::Settings)
     }
 }
 
 pub fn gameplay_setup(
     mut commands: Commands,
-    mut player_query: Query<&mut Transform, With<Player>>,
-    window_query: Query<&Window, With<PrimaryWindow>>,
-    candy_image: Res<CandyImage>,
-    audio: Res<Audio>,
-    audio_sinks: Res<Assets<AudioSink>>,
-    asset_server: Res<AssetServer>,
-    mut music: ResMut<Music>,
+    mut player_query: Query<(&Player, &mut Transform, &mut Interpolated, &mut Score)>,
+    player_count: Res<PlayerCount>,
+    arena: Res<Arena>,
+    player_image: Res<PlayerImage>,
+    mut round_stats: ResMut<RoundStats>,
+    mut rng: ResMut<GameRng>,
+    mut candy_serials: ResMut<CandySerials>,
+    mut timer: ResMut<CandySpawnTimer>,
+    time: Res<SimulationTime>,
+    levels: Res<Levels>,
+    playback: Option<Res<ReplayPlayback>>,
 ) {
     info!("gameplay_setup");
 
-    if let Ok(mut transform) = player_query.get_single_mut() {
+    let seed = rng.start_round();
+    let level = levels.current();
+    info!(
+        "round seed: {seed}, level {}: {}",
+        levels.current + 1,
+        level.name
+    );
+    timer.set_duration(Duration::from_secs_f32(level.candy_spawn_seconds));
+    timer.reset();
+
+    let mut candies_at_start = 0;
+    let mut scores_at_start: [Score; MAX_PLAYERS] = default();
+    for (player, mut transform, mut interpolated, mut score) in &mut player_query {
+        transform.translation = home_position(player.index, **player_count, &arena);
         transform.scale.x = 1.0;
         transform.scale.y = 1.0;
+        *interpolated = Interpolated::new(transform.translation);
+        // Scores carry over from one level to the next. A replay wasn't
+        // there for the levels before, so it brings its own.
+        if let Some(playback) = &playback {
+            *score = playback.score_at_start(player.index, time.elapsed_seconds());
+        } else if levels.current == 0 {
+            *score = Score::default();
+        }
+        candies_at_start += score.candies_eaten;
+        scores_at_start[player.index] = score.clone();
     }
+    for index in player_query.iter().len()..**player_count {
+        let translation = home_position(index, **player_count, &arena);
+        let score = playback.as_ref().map_or_else(Score::default, |playback| {
+            playback.score_at_start(index, time.elapsed_seconds())
+        });
+        candies_at_start += score.candies_eaten;
+        scores_at_start[index] = score.clone();
+        spawn_player(&mut commands, &player_image, index, translation, score);
+    }
+
+    // Like the scores, the run's time and growth so far carry over, and go
+    // back to nothing for a new run
+    let (duration_at_start, growth_at_start) = if playback.is_some() || levels.current == 0 {
+        (0.0, 0.0)
+    } else {
+        (round_stats.duration_at_start, round_stats.growth_at_start)
+    };
 
-    let window = window_query.get_single().unwrap();
+    *candy_serials = CandySerials::default();
+    *round_stats = RoundStats {
+        seed,
+        level: levels.current,
+        candies_at_start,
+        scores_at_start,
+        duration_at_start,
+        growth_at_start,
+        start_time: time.elapsed_seconds(),
+        ..default()
+    };
+}
 
-    for _ in 0..NUMBER_OF_INITIAL_CANDIES {
-        spawn_candy(&mut commands, window, &candy_image);
+pub fn gameplay_spawn_initial_candy(
+    mut commands: Commands,
+    arena: Res<Arena>,
+    candy_image: Res<CandyImage>,
+    mut rng: ResMut<GameRng>,
+    mut candy_serials: ResMut<CandySerials>,
+    levels: Res<Levels>,
+) {
+    let level = levels.current();
+    for _ in 0..level.initial_candies {
+        spawn_candy(
+            &mut commands,
+            &arena,
+            &candy_image,
+            &level.candy_weights,
+            &mut rng,
+            &mut candy_serials,
+        );
     }
+}
 
-    let weak_handle = audio.play_with_settings(
-        asset_server.load("music/music_gameplay.ogg"),
-        PlaybackSettings {
-            repeat: true,
-            volume: Default::default(),
-            speed: 1.0,
-        },
-    );
+pub fn gameplay_presentation_setup(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut mixer: Mixer,
+) {
+    commands.spawn((
+        TextBundle::from_section(
+            "",
+            TextStyle {
+                font: asset_server.load("fonts/MesloLGS NF Regular.ttf"),
+                font_size: 30.0,
+                color: Color::WHITE,
+            },
+        )
+        .with_text_alignment(TextAlignment::Left)
+        .with_style(Style {
+            position_type: PositionType::Absolute,
+            top: Val::Px(5.0),
+            left: Val::Px(15.0),
+            ..default()
+        }),
+        ScoreText {},
+    ));
 
-    let strong_handle = audio_sinks.get_handle(weak_handle);
+    commands.spawn((
+        TextBundle::from_section(
+            "",
+            TextStyle {
+                font: asset_server.load("fonts/MesloLGS NF Regular.ttf"),
+                font_size: 30.0,
+                color: Color::WHITE,
+            },
+        )
+        .with_text_alignment(TextAlignment::Right)
+        .with_style(Style {
+            position_type: PositionType::Absolute,
+            top: Val::Px(5.0),
+            right: Val::Px(15.0),
+            ..default()
+        }),
+        PowerUpText {},
+    ));
 
-    music.0 = Some(strong_handle);
+    mixer.play_music(asset_server.load("music/music_gameplay.ogg"));
 }
 
 pub fn gameplay_teardown(
     mut commands: Commands,
-    music: ResMut<Music>,
-    audio_sinks: Res<Assets<AudioSink>>,
     entities: Query<Entity, (Without<Camera>, Without<Window>, Without<Player>)>,
+    mut round_stats: ResMut<RoundStats>,
+    time: Res<SimulationTime>,
 ) {
     info!("gameplay_teardown");
 
+    round_stats.duration = time.elapsed_seconds() - round_stats.start_time;
+
     for entity in &entities {
         commands.entity(entity).despawn();
     }
+}
 
-    stop_music(music, audio_sinks);
+pub fn gameplay_stop_music(mut mixer: Mixer) {
+    mixer.stop_music();
 }
 
 pub fn gameplay_spawn_candy_timer(
     mut commands: Commands,
     query: Query<(&Transform, &Candy)>,
-    time: Res<Time>,
+    time: Res<SimulationTime>,
     mut timer: ResMut<CandySpawnTimer>,
-    window_query: Query<&Window, With<PrimaryWindow>>,
+    arena: Res<Arena>,
     candy_image: Res<CandyImage>,
-    keyboard_input: Res<Input<KeyCode>>,
+    input: Res<GameInput>,
+    mut rng: ResMut<GameRng>,
+    mut candy_serials: ResMut<CandySerials>,
+    levels: Res<Levels>,
 ) {
+    let level = levels.current();
     let candy_left = query.iter().len();
-    if candy_left > MAX_CANDY {
+    if candy_left > level.max_candy {
         return;
     }
-    let window = window_query.get_single().unwrap();
     timer.tick(time.delta());
     if timer.just_finished() {
-        spawn_candy(&mut commands, window, &candy_image);
+        spawn_candy(
+            &mut commands,
+            &arena,
+            &candy_image,
+            &level.candy_weights,
+            &mut rng,
+            &mut candy_serials,
+        );
     }
-    if keyboard_input.just_pressed(KeyCode::O) {
-        spawn_candy(&mut commands, window, &candy_image);
+    if input.debug_spawn {
+        spawn_candy(
+            &mut commands,
+            &arena,
+            &candy_image,
+            &level.candy_weights,
+            &mut rng,
+            &mut candy_serials,
+        );
     }
 }
 
-fn spawn_candy(commands: &mut Commands, window: &Window, candy_image: &CandyImage) {
-    let random_pos_x = rand::random::<f32>() * window.width() - window.width() / 2.0;
-    let random_pos_y = rand::random::<f32>() * window.height() - window.height() / 2.0;
-    let random_dir_x = (rand::random::<f32>() * 2.0) - 1.0;
-    let random_dir_y = (rand::random::<f32>() * 2.0) - 1.0;
+/// Spawns a candy of a kind picked by `weights`, somewhere in the arena
+fn spawn_candy(
+    commands: &mut Commands,
+    arena: &Arena,
+    candy_image: &CandyImage,
+    weights: &BTreeMap<CandyKind, u32>,
+    rng: &mut GameRng,
+    candy_serials: &mut CandySerials,
+) {
+    let rng = &mut rng.gameplay;
+    let kind = CandyKind::choose(weights, rng);
+    let random_pos_x = rng.gen::<f32>() * arena.width - arena.width / 2.0;
+    let random_pos_y = rng.gen::<f32>() * arena.height - arena.height / 2.0;
+    let random_dir_x = (rng.gen::<f32>() * 2.0) - 1.0;
+    let random_dir_y = (rng.gen::<f32>() * 2.0) - 1.0;
+
+    let translation = Vec3::new(random_pos_x, random_pos_y, 0.0);
+    let direction = Vec2::new(random_dir_x, random_dir_y).normalize();
+
+    spawn_candy_at(
+        commands,
+        candy_image,
+        candy_serials.next(),
+        kind,
+        translation,
+        direction,
+    );
+}
 
+fn spawn_candy_at<'w, 's, 'a>(
+    commands: &'a mut Commands<'w, 's>,
+    candy_image: &CandyImage,
+    serial: u32,
+    kind: CandyKind,
+    translation: Vec3,
+    direction: Vec2,
+) -> EntityCommands<'w, 's, 'a> {
+    let properties = kind.properties();
     commands.spawn((
         SpriteBundle {
-            transform: Transform::from_xyz(random_pos_x, random_pos_y, 0.0),
+            transform: Transform::from_translation(translation).with_scale(Vec3::new(
+                properties.size,
+                properties.size,
+                1.0,
+            )),
             texture: candy_image.0.clone(),
+            sprite: Sprite {
+                color: properties.color,
+                ..default()
+            },
             ..default()
         },
-        Candy {
-            direction: Vec2::new(random_dir_x, random_dir_y).normalize(),
-            timestamp_changed_direction: 0.0,
-        },
-    ));
+        Candy::new(serial, kind, direction),
+        BodySize(CANDY_SIZE),
+        Collider(collision::CANDY_SHAPE),
+        Interpolated::new(translation),
+    ))
 }
 
 pub fn gameplay_await_zero_candy(
@@ -496,95 +1322,206 @@ pub fn gameplay_await_zero_candy(
     }
 }
 
-pub fn gameplay_player_movement(
-    keyboard_input: Res<Input<KeyCode>>,
-    mut player_query: Query<&mut Transform, With<Player>>,
-    time: Res<Time>,
+/// Ends the round once the level's target is reached, and loses it when
+/// time is up or the candy reaches the level's cap
+pub fn gameplay_await_level_end(
+    score_query: Query<&Score, With<Player>>,
+    candy_query: Query<(), With<Candy>>,
+    levels: Res<Levels>,
+    mut round_stats: ResMut<RoundStats>,
+    time: Res<SimulationTime>,
+    mut next_state: ResMut<NextState<GameState>>,
 ) {
-    if let Ok(mut transform) = player_query.get_single_mut() {
-        let mut direction = Vec3::ZERO;
+    let level = levels.current();
+    if level_candies_eaten(score_query.iter(), &round_stats) >= level.target {
+        next_state.set(GameState::End);
+    } else if time.elapsed_seconds() - round_stats.start_time >= level.time_limit_seconds {
+        round_stats.game_over = Some(GameOverReason::TimeUp);
+        next_state.set(GameState::GameOver);
+    } else if candy_query.iter().len() >= level.max_candy {
+        round_stats.game_over = Some(GameOverReason::CandyOverflow);
+        next_state.set(GameState::GameOver);
+    }
+}
 
-        if keyboard_input.pressed(KeyCode::Left) || keyboard_input.pressed(KeyCode::A) {
-            direction += Vec3::new(-1.0, 0.0, 0.0);
-        }
-        if keyboard_input.pressed(KeyCode::Right) || keyboard_input.pressed(KeyCode::D) {
-            direction += Vec3::new(1.0, 0.0, 0.0);
-        }
-        if keyboard_input.pressed(KeyCode::Up) || keyboard_input.pressed(KeyCode::W) {
-            direction += Vec3::new(0.0, 1.0, 0.0);
-        }
-        if keyboard_input.pressed(KeyCode::Down) || keyboard_input.pressed(KeyCode::S) {
-            direction += Vec3::new(0.0, -1.0, 0.0);
-        }
+pub fn gameplay_apply_input(
+    input: Res<GameInput>,
+    mut player_query: Query<(&Player, &mut PlayerControl)>,
+) {
+    for (player, mut control) in &mut player_query {
+        control.direction = input.directions[player.index];
+        control.grow = input.debug_grow;
+    }
+}
 
-        if keyboard_input.pressed(KeyCode::P) {
+pub fn gameplay_player_movement(
+    mut player_query: Query<(&mut Transform, &PlayerControl, &PowerUps), With<Player>>,
+    time: Res<SimulationTime>,
+    config: Res<GameConfig>,
+) {
+    for (mut transform, control, power_ups) in &mut player_query {
+        if control.grow {
             transform.scale.x *= 1.1;
             transform.scale.y *= 1.1;
         }
 
-        transform.translation += direction * PLAYER_SPEED * time.delta_seconds();
+        let speed = if power_ups.is_active(PowerUpKind::SpeedBoost) {
+            config.player_speed * powerup::SPEED_BOOST_FACTOR
+        } else {
+            config.player_speed
+        };
+        transform.translation += control.direction.extend(0.0) * speed * time.delta_seconds();
     }
 }
 
-pub fn gameplay_exit_to_title(
-    keyboard_input: Res<Input<KeyCode>>,
-    mut next_state: ResMut<NextState<GameState>>,
+pub fn gameplay_update_score_text(
+    mut text_query: Query<&mut bevy::text::Text, With<ScoreText>>,
+    score_query: Query<(&Player, &Score)>,
+    candy_query: Query<(), With<Candy>>,
+    time: Res<SimulationTime>,
+    levels: Res<Levels>,
+    round_stats: Res<RoundStats>,
 ) {
-    if keyboard_input.pressed(KeyCode::Escape) {
+    let Ok(mut text) = text_query.get_single_mut() else {
+        return;
+    };
+    let mut scores: Vec<_> = score_query.iter().collect();
+    scores.sort_by_key(|(player, _)| player.index);
+    let now = time.elapsed_seconds();
+
+    let level = levels.current();
+    let time_left = (level.time_limit_seconds - (now - round_stats.start_time)).max(0.0);
+    let progress = format!(
+        "level {}: {}/{}  time: {:.0}\ncandy left: {}/{}",
+        levels.current + 1,
+        level_candies_eaten(scores.iter().map(|(_, score)| *score), &round_stats),
+        level.target,
+        time_left.ceil(),
+        candy_query.iter().len(),
+        level.max_candy,
+    );
+    let scores = match scores.as_slice() {
+        [(_, score)] => format!(
+            "score: {}  x{}\ncandy: {}",
+            score.points,
+            score.active_multiplier(now),
+            score.candies_eaten,
+        ),
+        _ => scores
+            .iter()
+            .map(|(player, score)| {
+                format!(
+                    "P{}: {}  x{}  candy: {}",
+                    player.index + 1,
+                    score.points,
+                    score.active_multiplier(now),
+                    score.candies_eaten,
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n"),
+    };
+    text.sections[0].value = format!("{scores}\n{progress}");
+    text.sections[0].style.color = if time_left <= COUNTDOWN_WARNING_SECONDS {
+        Color::RED
+    } else {
+        Color::WHITE
+    };
+}
+
+pub fn gameplay_exit_to_title(input: Res<GameInput>, mut next_state: ResMut<NextState<GameState>>) {
+    if input.quit {
         next_state.set(GameState::Title);
     }
-    if keyboard_input.pressed(KeyCode::Return) {
+    if input.restart {
+        next_state.set(GameState::Restarting);
+    }
+    if input.force_end {
         next_state.set(GameState::End);
     }
 }
 
+/// Starts the level over, with the scores the caticorns had when it began
+pub fn restart_setup(
+    mut score_query: Query<(&Player, &mut Score)>,
+    round_stats: Res<RoundStats>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    info!("restart_setup");
+
+    for (player, mut score) in &mut score_query {
+        *score = round_stats.scores_at_start[player.index].clone();
+    }
+    next_state.set(GameState::Playing);
+}
+
 pub fn gameplay_candy_movement(
-    mut candy_query: Query<(&mut Transform, &Candy), With<Candy>>,
-    player_query: Query<&Transform, (With<Player>, Without<Candy>)>,
-    time: Res<Time>,
+    mut candy_query: Query<(&mut Transform, &mut Candy)>,
+    player_query: Query<(&Transform, &PowerUps), (With<Player>, Without<Candy>)>,
+    time: Res<SimulationTime>,
+    levels: Res<Levels>,
+    config: Res<GameConfig>,
 ) {
-    let Ok(player_transform) = player_query.get_single() else {
-        error!("player query failed");
+    let frozen = player_query
+        .iter()
+        .any(|(_, power_ups)| power_ups.is_active(PowerUpKind::Freeze));
+    if frozen {
         return;
-    };
-    for (mut transform, candy) in candy_query.iter_mut() {
-        let direction = Vec3::new(candy.direction.x, candy.direction.y, 0.0);
-        transform.translation += direction * CANDY_SPEED * time.delta_seconds();
-
-        let mut distance = transform.translation.distance(player_transform.translation);
-        if distance < 200.0 {
-            if distance < 25.0 {
-                distance = 25.0;
-            }
-            let direction = Vec3::new(
-                transform.translation.x - player_transform.translation.x,
-                transform.translation.y - player_transform.translation.y,
-                0.0,
-            )
-            .normalize();
-            let force = 400.0 - distance;
+    }
+
+    let level = levels.current();
+    for (mut transform, mut candy) in candy_query.iter_mut() {
+        let properties = candy.kind.properties();
+        let direction = candy.heading().extend(0.0);
+        transform.translation +=
+            direction * level.candy_speed * properties.speed * time.delta_seconds();
+        candy.age += time.delta_seconds();
+
+        // Fleeing candy notices caticorns from further away, and runs harder
+        let (range, push) = match properties.behavior {
+            CandyBehavior::Flee => (config.flee_range, config.flee_force),
+            _ => (config.push_range, config.push_force),
+        };
 
-            transform.translation += direction * time.delta_seconds() * force;
+        // Every caticorn nearby pushes the candy away, or pulls it in with a magnet
+        for (player_transform, power_ups) in &player_query {
+            let mut distance = transform.translation.distance(player_transform.translation);
+            if distance < range {
+                if distance < 25.0 {
+                    distance = 25.0;
+                }
+                let direction = Vec3::new(
+                    transform.translation.x - player_transform.translation.x,
+                    transform.translation.y - player_transform.translation.y,
+                    0.0,
+                )
+                .normalize();
+                let mut force = push - distance;
+                if power_ups.is_active(PowerUpKind::Magnet) {
+                    force = -force;
+                }
+
+                transform.translation += direction * time.delta_seconds() * force;
+            }
         }
     }
 }
 
 pub fn gameplay_update_candy_direction(
-    mut q: Query<(&Transform, &Handle<Image>, &mut Candy)>,
-    window_query: Query<&Window, With<PrimaryWindow>>,
-    audio: Res<Audio>,
-    sound: Res<CandyChangeDirectionSound>,
-    images: Res<Assets<Image>>,
-    time: Res<Time>,
+    mut commands: Commands,
+    mut q: Query<(Entity, &Transform, &BodySize, &mut Candy)>,
+    arena: Res<Arena>,
+    candy_image: Res<CandyImage>,
+    mut candy_serials: ResMut<CandySerials>,
+    mut sounds: EventWriter<GameSound>,
+    time: Res<SimulationTime>,
 ) {
-    let window = window_query.get_single().unwrap();
-
-    for (transform, image_handle, mut candy) in q.iter_mut() {
-        let Some(image) = images.get(image_handle) else {
-            continue;
-        };
-
-        let rect = calculate_confinement_rect(window, image, transform);
+    // In spawn order, so that the halves of split candy get the same serials
+    // every run
+    let mut candies: Vec<_> = q.iter_mut().collect();
+    candies.sort_by_key(|(.., candy)| candy.serial);
+    for (entity, transform, size, mut candy) in candies {
+        let rect = calculate_confinement_rect(&arena, **size, transform);
 
         let mut changed_direction = false;
         let pos = transform.translation;
@@ -601,126 +1538,418 @@ pub fn gameplay_update_candy_direction(
 
         if changed_direction {
             if time.elapsed_seconds() - candy.timestamp_changed_direction > 0.1 {
-                audio.play(sound.select_random());
+                sounds.send(GameSound::CandyBounce(pos.truncate()));
             } else {
             }
             candy.timestamp_changed_direction = time.elapsed_seconds();
+
+            if candy.kind.properties().behavior == CandyBehavior::Split {
+                // Both halves start just inside the walls, heading away from them
+                let translation = Vec3::new(
+                    pos.x.clamp(rect.min_x + 1.0, rect.max_x - 1.0),
+                    pos.y.clamp(rect.min_y + 1.0, rect.max_y - 1.0),
+                    0.0,
+                );
+                for angle in [candy::SPLIT_ANGLE, -candy::SPLIT_ANGLE] {
+                    let direction = Vec2::from_angle(angle).rotate(candy.direction);
+                    spawn_candy_at(
+                        &mut commands,
+                        &candy_image,
+                        candy_serials.next(),
+                        CandyKind::Donut,
+                        translation,
+                        direction,
+                    );
+                }
+                commands.entity(entity).despawn();
+            }
         }
     }
 }
 
-pub fn gameplay_confine_entity_movement(
-    mut query: Query<(&mut Transform, &Handle<Image>)>,
-    window_query: Query<&Window, With<PrimaryWindow>>,
-    images: Res<Assets<Image>>,
+/// Candies bounce off each other like balls of the same weight, trading the
+/// part of their velocity that points at the other one
+pub fn gameplay_candy_collision(
+    mut candy_query: Query<(Entity, &Collider, &mut Transform, &mut Candy)>,
+    player_query: Query<&PowerUps, With<Player>>,
+    index: Res<SpatialIndex>,
+    mut sounds: EventWriter<GameSound>,
+    time: Res<SimulationTime>,
 ) {
-    let window = window_query.get_single().unwrap();
-    for (mut transform, image_handle) in query.iter_mut() {
-        let Some(image) = images.get(image_handle) else {
+    let frozen = player_query
+        .iter()
+        .any(|power_ups| power_ups.is_active(PowerUpKind::Freeze));
+    if frozen {
+        return;
+    }
+
+    let now = time.elapsed_seconds();
+    // Each pair moves the candies it resolves, so pairs go in spawn order for
+    // every run of the round to end up in the same place
+    let mut candies: Vec<(u32, Entity)> = candy_query
+        .iter()
+        .map(|(entity, .., candy)| (candy.serial, entity))
+        .collect();
+    candies.sort_unstable();
+    for (serial, entity) in candies {
+        let Ok((_, &collider, transform, _)) = candy_query.get(entity) else {
             continue;
         };
+        let position = transform.translation.truncate();
+        let reach = collider.reach(transform);
+
+        let mut others: Vec<(u32, Entity)> = index
+            .candy
+            .near(position, reach)
+            .filter_map(|other_entity| {
+                let (.., other) = candy_query.get(other_entity).ok()?;
+                // Every pair once
+                (other.serial > serial).then_some((other.serial, other_entity))
+            })
+            .collect();
+        others.sort_unstable();
+        for (_, other_entity) in others {
+            let Ok(
+                [(_, _, mut transform, mut candy), (_, other_collider, mut other_transform, mut other)],
+            ) = candy_query.get_many_mut([entity, other_entity])
+            else {
+                continue;
+            };
+            let Some((normal, depth)) =
+                collider.contact(&transform, other_collider, &other_transform)
+            else {
+                continue;
+            };
+
+            // Each gives way by half the overlap
+            transform.translation -= (normal * depth / 2.0).extend(0.0);
+            other_transform.translation += (normal * depth / 2.0).extend(0.0);
+
+            let velocity = candy.direction * candy.kind.properties().speed;
+            let other_velocity = other.direction * other.kind.properties().speed;
+            let closing = (velocity - other_velocity).dot(normal);
+            if closing <= 0.0 {
+                // Already moving apart
+                continue;
+            }
+            // Candy keeps the speed of its kind, only its direction changes.
+            // One that would come to a stop bounces straight back instead.
+            candy.direction = (velocity - closing * normal)
+                .try_normalize()
+                .unwrap_or(-normal);
+            other.direction = (other_velocity + closing * normal)
+                .try_normalize()
+                .unwrap_or(normal);
+
+            if now - candy.timestamp_changed_direction > 0.1
+                && now - other.timestamp_changed_direction > 0.1
+            {
+                let between = (transform.translation + other_transform.translation) / 2.0;
+                sounds.send(GameSound::CandyBounce(between.truncate()));
+            }
+            candy.timestamp_changed_direction = now;
+            other.timestamp_changed_direction = now;
+        }
+    }
+}
 
-        let rect = calculate_confinement_rect(window, image, &transform);
+pub fn gameplay_confine_entity_movement(
+    mut query: Query<(&mut Transform, &BodySize, Option<&Player>)>,
+    arena: Res<Arena>,
+    config: Res<GameConfig>,
+) {
+    for (mut transform, size, player) in query.iter_mut() {
+        let rect = calculate_confinement_rect(&arena, **size, &transform);
 
         transform.translation.x = transform.translation.x.clamp(rect.min_x, rect.max_x);
         transform.translation.y = transform.translation.y.clamp(rect.min_y, rect.max_y);
-        transform.scale.x = transform.scale.x.clamp(1.0, 6.0);
-        transform.scale.y = transform.scale.y.clamp(1.0, 6.0);
+        if player.is_some() {
+            transform.scale.x = transform.scale.x.clamp(config.min_scale, config.max_scale);
+            transform.scale.y = transform.scale.y.clamp(config.min_scale, config.max_scale);
+        }
     }
 }
 
 pub fn gameplay_player_candy_collision(
     mut commands: Commands,
-    mut player_query: Query<(&Handle<Image>, &mut Transform), (With<Player>, Without<Candy>)>,
-    candy_query: Query<(Entity, &Handle<Image>, &Transform), (With<Candy>, Without<Player>)>,
-    audio: Res<Audio>,
-    sound: Res<PlayerCandyCollisionSound>,
-    images: Res<Assets<Image>>,
-) {
-    if let Ok((player_image_handle, mut player_transform)) = player_query.get_single_mut() {
-        let Some(player_image) = images.get(player_image_handle) else {
-            error!("failed to get player image");
-            return;
-        };
-        for (candy_entity, candy_image_handle, candy_transform) in candy_query.iter() {
-            let Some(candy_image) = images.get(candy_image_handle) else {
+    mut player_query: Query<(&Player, &Collider, &mut Transform, &mut Score), Without<Candy>>,
+    candy_query: Query<(&Candy, &Collider, &Transform), Without<Player>>,
+    index: Res<SpatialIndex>,
+    mut sounds: EventWriter<GameSound>,
+    time: Res<SimulationTime>,
+    config: Res<GameConfig>,
+) {
+    // The first caticorn gets the candy when two reach it together
+    let mut players: Vec<_> = player_query.iter_mut().collect();
+    players.sort_by_key(|(player, ..)| player.index);
+
+    let mut eaten = Vec::new();
+    for (_, player_collider, player_transform, score) in &mut players {
+        let position = player_transform.translation.truncate();
+        let reach = player_collider.reach(player_transform);
+        for candy_entity in index.candy.near(position, reach) {
+            // A candy can only be eaten once
+            if eaten.contains(&candy_entity) {
+                continue;
+            }
+            let Ok((candy, candy_collider, candy_transform)) = candy_query.get(candy_entity) else {
                 continue;
             };
-            let mut distance = player_transform
-                .translation
-                .distance(candy_transform.translation);
-            let half_size_player = player_image.size().x * player_transform.scale.x / 2.0;
-            let half_size_candy = candy_image.size().x * candy_transform.scale.x / 2.0;
-            distance -= half_size_player;
-            distance -= half_size_candy;
-            if distance <= -20.0 {
-                audio.play(sound.clone());
+            if player_collider.overlaps(player_transform, candy_collider, candy_transform) {
+                sounds.send(GameSound::PlayerEat(candy_transform.translation.truncate()));
                 commands.entity(candy_entity).despawn();
-                player_transform.scale.x += 0.03;
-                player_transform.scale.y += 0.03;
+                eaten.push(candy_entity);
+                feed_player(
+                    player_transform,
+                    score,
+                    candy.kind,
+                    time.elapsed_seconds(),
+                    &config,
+                );
             }
         }
     }
 }
 
+pub fn play_game_sounds(
+    mut events: EventReader<GameSound>,
+    mut mixer: Mixer,
+    candy_bounce_sound: Res<CandyChangeDirectionSound>,
+    player_eat_sound: Res<PlayerCandyCollisionSound>,
+    power_up_sound: Res<PowerUpSound>,
+    mut rng: ResMut<GameRng>,
+    player_query: Query<&Transform, With<Player>>,
+    arena: Res<Arena>,
+) {
+    for event in events.iter() {
+        let (sound, position) = match *event {
+            GameSound::CandyBounce(position) => {
+                (candy_bounce_sound.select_random(&mut rng.effects), position)
+            }
+            GameSound::PlayerEat(position) => (player_eat_sound.clone(), position),
+            GameSound::PowerUp => {
+                mixer.play_sfx(power_up_sound.clone());
+                continue;
+            }
+        };
+        // Panned by where the sound is across the arena, and quieter the
+        // further it is from the nearest caticorn
+        let pan = position.x / (arena.width / 2.0);
+        let distance = player_query
+            .iter()
+            .map(|transform| transform.translation.truncate().distance(position))
+            .reduce(f32::min)
+            .unwrap_or(0.0);
+        mixer.play_sfx_from(sound, pan, distance);
+    }
+}
+
+pub fn end_setup(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    score_query: Query<(&Player, &Score)>,
+    round_stats: Res<RoundStats>,
+    levels: Res<Levels>,
+) {
+    info!("end_setup");
+
+    let mut scores: Vec<_> = score_query.iter().collect();
+    scores.sort_by_key(|(player, _)| player.index);
+
+    for (player, score) in &scores {
+        info!(
+            "player {} final score: {} ({} candies, best combo x{}, seed {})",
+            player.index + 1,
+            score.points,
+            score.candies_eaten,
+            score.best_combo,
+            round_stats.seed
+        );
+    }
+
+    let tally = match scores.as_slice() {
+        [(_, score)] => format!(
+            "score: {}\ncandy eaten: {}\nbest combo: x{}",
+            score.points, score.candies_eaten, score.best_combo,
+        ),
+        _ => {
+            let best = scores.iter().map(|(_, score)| score.points).max();
+            let winners: Vec<_> = scores
+                .iter()
+                .filter(|(_, score)| Some(score.points) == best)
+                .collect();
+            let announcement = match winners.as_slice() {
+                [(player, _)] => format!("player {} wins!", player.index + 1),
+                _ => "it's a draw!".to_string(),
+            };
+            let lines: Vec<_> = scores
+                .iter()
+                .map(|(player, score)| {
+                    format!(
+                        "P{}: {}  candy: {}  best combo: x{}",
+                        player.index + 1,
+                        score.points,
+                        score.candies_eaten,
+                        score.best_combo,
+                    )
+                })
+                .collect();
+            format!("{announcement}\n{}", lines.join("\n"))
+        }
+    };
+    let cleared = level_candies_eaten(scores.iter().map(|(_, score)| *score), &round_stats)
+        >= levels.current().target;
+    let tally = format!(
+        "level {} {}\n{tally}",
+        levels.current + 1,
+        if cleared { "cleared!" } else { "not cleared" },
+    );
+
+    commands.spawn((
+        TextBundle::from_section(
+            tally,
+            TextStyle {
+                font: asset_server.load("fonts/MesloLGS NF Regular.ttf"),
+                font_size: 30.0,
+                color: Color::WHITE,
+            },
+        )
+        .with_text_alignment(TextAlignment::Left)
+        .with_style(Style {
+            position_type: PositionType::Absolute,
+            top: Val::Px(5.0),
+            left: Val::Px(15.0),
+            ..default()
+        }),
+        Text {},
+    ));
+}
+
 pub fn end_sequence(
-    mut player_query: Query<&mut Transform, (With<Player>, Without<Candy>)>,
-    time: Res<Time>,
+    mut player_query: Query<(&Player, &mut Transform), Without<Candy>>,
+    player_count: Res<PlayerCount>,
+    arena: Res<Arena>,
+    time: Res<SimulationTime>,
     mut next_state: ResMut<NextState<GameState>>,
 ) {
     debug!("end_sequence");
 
-    if let Ok(mut transform) = player_query.get_single_mut() {
-        let direction_to_mid = Vec3::new(
-            0.0 - transform.translation.x,
-            0.0 - transform.translation.y,
+    let mut all_home = true;
+    for (player, mut transform) in &mut player_query {
+        let home = home_position(player.index, **player_count, &arena);
+        let direction_to_home = Vec3::new(
+            home.x - transform.translation.x,
+            home.y - transform.translation.y,
             0.0,
         );
-        if direction_to_mid.length() < 1.0 {
-            next_state.set(GameState::Poop);
-        } else {
-            let mut change = direction_to_mid.normalize() * time.delta_seconds() * 400.0;
-            while change.length() > direction_to_mid.length() {
+        if direction_to_home.length() >= 1.0 {
+            all_home = false;
+            let mut change = direction_to_home.normalize() * time.delta_seconds() * 400.0;
+            while change.length() > direction_to_home.length() {
                 change *= 0.9;
             }
             transform.translation += change;
         }
     }
+    if all_home {
+        next_state.set(GameState::Poop);
+    }
 }
 
 pub fn poop_setup(
-    mut player_query: Query<&Transform, (With<Player>, Without<Candy>)>,
+    player_query: Query<(&Player, &Transform), Without<Candy>>,
     mut commands: Commands,
-    audio: Res<Audio>,
+    mut mixer: Mixer,
     asset_server: Res<AssetServer>,
+    mut round_stats: ResMut<RoundStats>,
 ) {
     info!("poop_setup");
-    audio.play(asset_server.load("audio/end_fart.ogg"));
-    if let Ok(transform) = player_query.get_single_mut() {
-        commands.insert_resource(ShrinkData {
-            initial_scale_x: transform.scale.x,
-            total_time: 0.0,
-        });
+    mixer.play_sfx(asset_server.load("audio/end_fart.ogg"));
+
+    let mut initial_scales = [1.0; MAX_PLAYERS];
+    for (player, transform) in &player_query {
+        initial_scales[player.index] = transform.scale.x;
     }
+    round_stats.final_scale = initial_scales[0];
+    commands.insert_resource(ShrinkData {
+        initial_scales,
+        total_time: 0.0,
+    });
 }
 
 pub fn poop_sequence(
-    mut player_query: Query<&mut Transform, (With<Player>, Without<Candy>)>,
+    mut commands: Commands,
+    mut player_query: Query<(&Player, &mut Transform, &Score), Without<Candy>>,
     time: Res<Time>,
     mut next_state: ResMut<NextState<GameState>>,
     mut shrink_data: ResMut<ShrinkData>,
+    player_count: Res<PlayerCount>,
+    mut round_stats: ResMut<RoundStats>,
+    high_scores: Res<HighScores>,
+    replay: Option<Res<ReplayPlayback>>,
+    session: Option<Res<network::NetworkSession>>,
+    mut levels: ResMut<Levels>,
 ) {
-    if let Ok(mut transform) = player_query.get_single_mut() {
-        let shrink = (shrink_data.initial_scale_x - 1.0) / 2.0;
+    let mut solo_score = None;
+    let mut candies_eaten = 0;
+    for (player, mut transform, score) in &mut player_query {
+        candies_eaten += score.candies_eaten;
+        let shrink = (shrink_data.initial_scales[player.index] - 1.0) / 2.0;
 
         transform.scale.x -= shrink * time.delta_seconds();
         transform.scale.y -= shrink * time.delta_seconds();
 
-        if shrink_data.total_time > 2.0 {
-            next_state.set(GameState::Title);
+        if **player_count == 1 {
+            solo_score = Some(score);
         }
+    }
 
-        shrink_data.total_time += time.delta_seconds();
+    if shrink_data.total_time > 2.0 {
+        let cleared =
+            candies_eaten.saturating_sub(round_stats.candies_at_start) >= levels.current().target;
+        // Online rounds and replays are a single level
+        if cleared && network::offline(session) && replay.is_none() && levels.advance() {
+            round_stats.carry_over();
+            next_state.set(GameState::Playing);
+            return;
+        }
+        end_run(
+            &mut commands,
+            &mut next_state,
+            solo_score,
+            &round_stats,
+            &high_scores,
+            replay.is_some(),
+        );
+    }
+
+    shrink_data.total_time += time.delta_seconds();
+}
+
+/// Back to the title, by way of the name entry if the score makes it into
+/// the high score table
+fn end_run(
+    commands: &mut Commands,
+    next_state: &mut NextState<GameState>,
+    solo_score: Option<&Score>,
+    round_stats: &RoundStats,
+    high_scores: &HighScores,
+    is_replay: bool,
+) {
+    // The high score table is for caticorns eating on their own
+    match solo_score {
+        Some(score) if !is_replay && high_scores.qualifies(score.points) => {
+            commands.insert_resource(PendingHighScore(HighScoreEntry {
+                name: String::new(),
+                points: score.points,
+                candies_eaten: score.candies_eaten,
+                final_scale: round_stats.run_final_scale(),
+                duration_seconds: round_stats.run_duration(),
+                date: storage::today(),
+            }));
+            next_state.set(GameState::NameEntry);
+        }
+        _ => next_state.set(GameState::Title),
     }
 }
 
@@ -733,3 +1962,117 @@ pub fn poop_teardown(
         commands.entity(entity).despawn();
     }
 }
+
+pub fn game_over_setup(
+    mut commands: Commands,
+    player_query: Query<(&Player, &Transform, &Score), Without<Candy>>,
+    mut mixer: Mixer,
+    asset_server: Res<AssetServer>,
+    mut round_stats: ResMut<RoundStats>,
+    levels: Res<Levels>,
+) {
+    info!("game_over_setup");
+
+    // The fart of defeat is a slow one
+    mixer.play_sfx_at_speed(asset_server.load("audio/end_fart.ogg"), 0.5);
+
+    let mut scores: Vec<_> = player_query.iter().collect();
+    scores.sort_by_key(|(player, _, _)| player.index);
+
+    let mut initial_scales = [1.0; MAX_PLAYERS];
+    for (player, transform, _) in &scores {
+        initial_scales[player.index] = transform.scale.x;
+    }
+    round_stats.final_scale = initial_scales[0];
+    commands.insert_resource(ShrinkData {
+        initial_scales,
+        total_time: 0.0,
+    });
+
+    let reason = round_stats.game_over.map_or("", |reason| reason.describe());
+    info!(
+        "game over on level {} (seed {}): {reason}",
+        levels.current + 1,
+        round_stats.seed
+    );
+    let tally = match scores.as_slice() {
+        [(_, _, score)] => format!("score: {}", score.points),
+        _ => scores
+            .iter()
+            .map(|(player, _, score)| format!("P{}: {}", player.index + 1, score.points))
+            .collect::<Vec<_>>()
+            .join("\n"),
+    };
+
+    commands.spawn((
+        TextBundle::from_section(
+            format!("game over\n{reason}\nlevel {}\n{tally}", levels.current + 1),
+            TextStyle {
+                font: asset_server.load("fonts/MesloLGS NF Regular.ttf"),
+                font_size: 30.0,
+                color: Color::WHITE,
+            },
+        )
+        .with_text_alignment(TextAlignment::Left)
+        .with_style(Style {
+            position_type: PositionType::Absolute,
+            top: Val::Px(5.0),
+            left: Val::Px(15.0),
+            ..default()
+        }),
+        Text {},
+    ));
+}
+
+/// The caticorns deflate, spinning, where they stood when the round was lost
+pub fn game_over_sequence(
+    mut commands: Commands,
+    mut player_query: Query<(&Player, &mut Transform, &Score), Without<Candy>>,
+    time: Res<Time>,
+    mut next_state: ResMut<NextState<GameState>>,
+    mut shrink_data: ResMut<ShrinkData>,
+    player_count: Res<PlayerCount>,
+    round_stats: Res<RoundStats>,
+    high_scores: Res<HighScores>,
+    replay: Option<Res<ReplayPlayback>>,
+) {
+    let mut solo_score = None;
+    for (player, mut transform, score) in &mut player_query {
+        let shrink = (shrink_data.initial_scales[player.index] - 1.0) / GAME_OVER_SECONDS;
+
+        transform.scale.x = (transform.scale.x - shrink * time.delta_seconds()).max(1.0);
+        transform.scale.y = (transform.scale.y - shrink * time.delta_seconds()).max(1.0);
+        transform.rotate_z(std::f32::consts::TAU * time.delta_seconds());
+
+        if **player_count == 1 {
+            solo_score = Some(score);
+        }
+    }
+
+    if shrink_data.total_time > GAME_OVER_SECONDS {
+        end_run(
+            &mut commands,
+            &mut next_state,
+            solo_score,
+            &round_stats,
+            &high_scores,
+            replay.is_some(),
+        );
+    }
+
+    shrink_data.total_time += time.delta_seconds();
+}
+
+pub fn game_over_teardown(
+    mut commands: Commands,
+    entities: Query<Entity, (Without<Camera>, Without<Window>, Without<Player>)>,
+    mut player_query: Query<&mut Transform, With<Player>>,
+) {
+    info!("game_over_teardown");
+    for entity in &entities {
+        commands.entity(entity).despawn();
+    }
+    for mut transform in &mut player_query {
+        transform.rotation = Quat::IDENTITY;
+    }
+}
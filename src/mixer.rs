@@ -0,0 +1,420 @@
+//! Everything the game plays goes through here, on one of two channels:
+//! music, which crossfades from one track to the next, and sound effects,
+//! of which only so many play at once and which can come from the left or
+//! the right. Each channel follows its volume in the [`Settings`].
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use bevy::asset::HandleId;
+use bevy::audio::{Source, Volume};
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+use bevy::reflect::{TypePath, TypeUuid};
+
+use crate::settings::Settings;
+
+/// How long one track takes to fade out while the next fades in
+const CROSSFADE_SECONDS: f32 = 1.0;
+
+/// Sound effects playing at the same time. Starting one more stops the
+/// oldest still playing.
+const MAX_SFX_VOICES: usize = 8;
+
+/// The same sound effect isn't started again this soon, so that a pile of
+/// candy hitting the wall at once sounds like one hit and not a buzz
+const SFX_RETRIGGER_SECONDS: f32 = 0.05;
+
+/// Sound effects this far from the listener play at about half volume
+const HEARING_DISTANCE: f32 = 400.0;
+
+/// How far into its track a music sink is, counted by the decoder feeding it
+#[derive(Default)]
+struct PlaybackPosition {
+    /// Samples decoded since the top of the track, all channels together
+    samples: AtomicU64,
+    /// Sample rate times channels of the track
+    samples_per_second: AtomicU32,
+}
+
+impl PlaybackPosition {
+    /// Seconds into the track. The audio device takes samples a little ahead
+    /// of playing them, so this runs early by about its buffer.
+    fn seconds(&self) -> f32 {
+        let samples_per_second = self.samples_per_second.load(Ordering::Relaxed);
+        if samples_per_second == 0 {
+            return 0.0;
+        }
+        (self.samples.load(Ordering::Relaxed) as f64 / samples_per_second as f64) as f32
+    }
+}
+
+/// A music track as handed to the audio device: it starts over from the top
+/// by itself, so that every sample of every loop goes through its decoder
+/// and gets counted. Bevy's own repeat plays later loops back from a buffer.
+#[derive(TypeUuid, TypePath)]
+#[uuid = "29592be6-6dc6-4d7c-a97f-1d72baf8ce6d"]
+pub struct MusicSource {
+    audio: AudioSource,
+    position: Arc<PlaybackPosition>,
+}
+
+impl Decodable for MusicSource {
+    type DecoderItem = <AudioSource as Decodable>::DecoderItem;
+    type Decoder = MusicDecoder;
+
+    fn decoder(&self) -> Self::Decoder {
+        let mut decoder = MusicDecoder {
+            audio: self.audio.clone(),
+            inner: self.audio.decoder(),
+            position: self.position.clone(),
+        };
+        decoder.start_over();
+        decoder
+    }
+}
+
+pub struct MusicDecoder {
+    audio: AudioSource,
+    inner: <AudioSource as Decodable>::Decoder,
+    position: Arc<PlaybackPosition>,
+}
+
+impl MusicDecoder {
+    fn start_over(&mut self) {
+        self.inner = self.audio.decoder();
+        let samples_per_second = self.inner.sample_rate() * self.inner.channels() as u32;
+        self.position
+            .samples_per_second
+            .store(samples_per_second, Ordering::Relaxed);
+        self.position.samples.store(0, Ordering::Relaxed);
+    }
+}
+
+impl Iterator for MusicDecoder {
+    type Item = <AudioSource as Decodable>::DecoderItem;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let sample = match self.inner.next() {
+            Some(sample) => sample,
+            None => {
+                self.start_over();
+                self.inner.next()?
+            }
+        };
+        self.position.samples.fetch_add(1, Ordering::Relaxed);
+        Some(sample)
+    }
+}
+
+impl Source for MusicDecoder {
+    fn current_frame_len(&self) -> Option<usize> {
+        // Every loop is the same file, so the end of one is no change of format
+        match self.inner.current_frame_len() {
+            Some(0) => None,
+            len => len,
+        }
+    }
+
+    fn channels(&self) -> u16 {
+        self.inner.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.inner.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+struct MusicTrack {
+    source: Handle<AudioSource>,
+    /// Nothing until the source has loaded and the track has started
+    sink: Option<Handle<AudioSink>>,
+    position: Arc<PlaybackPosition>,
+    /// Where the fade has got to, from 0 to 1
+    gain: f32,
+    fading_out: bool,
+}
+
+enum Voice {
+    Centered(Handle<AudioSink>),
+    Placed(Handle<SpatialAudioSink>),
+}
+
+impl Voice {
+    fn set_volume(
+        &self,
+        audio_sinks: &Assets<AudioSink>,
+        spatial_audio_sinks: &Assets<SpatialAudioSink>,
+        volume: f32,
+    ) {
+        match self {
+            Voice::Centered(handle) => {
+                if let Some(sink) = audio_sinks.get(handle) {
+                    sink.set_volume(volume);
+                }
+            }
+            Voice::Placed(handle) => {
+                if let Some(sink) = spatial_audio_sinks.get(handle) {
+                    sink.set_volume(volume);
+                }
+            }
+        }
+    }
+
+    /// Whether the sound effect has played to the end. One whose sink isn't
+    /// there yet has yet to start.
+    fn finished(
+        &self,
+        audio_sinks: &Assets<AudioSink>,
+        spatial_audio_sinks: &Assets<SpatialAudioSink>,
+    ) -> bool {
+        match self {
+            Voice::Centered(handle) => audio_sinks.get(handle).is_some_and(|sink| sink.empty()),
+            Voice::Placed(handle) => spatial_audio_sinks
+                .get(handle)
+                .is_some_and(|sink| sink.empty()),
+        }
+    }
+
+    fn stop(
+        &self,
+        audio_sinks: &Assets<AudioSink>,
+        spatial_audio_sinks: &Assets<SpatialAudioSink>,
+    ) {
+        match self {
+            Voice::Centered(handle) => {
+                if let Some(sink) = audio_sinks.get(handle) {
+                    sink.stop();
+                }
+            }
+            Voice::Placed(handle) => {
+                if let Some(sink) = spatial_audio_sinks.get(handle) {
+                    sink.stop();
+                }
+            }
+        }
+    }
+}
+
+#[derive(Resource, Default)]
+pub struct Channels {
+    /// The track playing, or fading in, last. The ones before it are fading
+    /// out.
+    music: Vec<MusicTrack>,
+    music_paused: bool,
+    /// Oldest first
+    sfx: VecDeque<Voice>,
+    sfx_started: HashMap<HandleId, f32>,
+}
+
+impl Channels {
+    /// The track playing last and how far into it the music is, in seconds
+    pub fn music_position(&self) -> Option<(&Handle<AudioSource>, f32)> {
+        self.music
+            .last()
+            .filter(|track| !track.fading_out)
+            .map(|track| (&track.source, track.position.seconds()))
+    }
+}
+
+/// Plays music and sound effects on their channels
+#[derive(SystemParam)]
+pub struct Mixer<'w> {
+    audio: Res<'w, Audio>,
+    audio_sinks: Res<'w, Assets<AudioSink>>,
+    spatial_audio_sinks: Res<'w, Assets<SpatialAudioSink>>,
+    channels: ResMut<'w, Channels>,
+    settings: Res<'w, Settings>,
+    time: Res<'w, Time>,
+}
+
+impl<'w> Mixer<'w> {
+    /// Fades whatever is playing out and `source` in, looping it. It starts
+    /// once it has loaded.
+    pub fn play_music(&mut self, source: Handle<AudioSource>) {
+        self.stop_music();
+        self.channels.music.push(MusicTrack {
+            source,
+            sink: None,
+            position: Arc::default(),
+            gain: 0.0,
+            fading_out: false,
+        });
+    }
+
+    /// Fades the music out
+    pub fn stop_music(&mut self) {
+        for track in &mut self.channels.music {
+            track.fading_out = true;
+        }
+    }
+
+    /// Holds the music, fades included, where it is
+    pub fn pause_music(&mut self) {
+        self.channels.music_paused = true;
+        for track in &self.channels.music {
+            if let Some(sink) = track
+                .sink
+                .as_ref()
+                .and_then(|sink| self.audio_sinks.get(sink))
+            {
+                sink.pause();
+            }
+        }
+    }
+
+    pub fn resume_music(&mut self) {
+        self.channels.music_paused = false;
+        for track in &self.channels.music {
+            if let Some(sink) = track
+                .sink
+                .as_ref()
+                .and_then(|sink| self.audio_sinks.get(sink))
+            {
+                sink.play();
+            }
+        }
+    }
+
+    pub fn play_sfx(&mut self, source: Handle<AudioSource>) {
+        self.play_sfx_at_speed(source, 1.0);
+    }
+
+    pub fn play_sfx_at_speed(&mut self, source: Handle<AudioSource>, speed: f32) {
+        if !self.start_voice(&source) {
+            return;
+        }
+        let weak_handle = self
+            .audio
+            .play_with_settings(source, self.sfx_playback_settings(speed));
+        let strong_handle = self.audio_sinks.get_handle(weak_handle);
+        self.channels.sfx.push_back(Voice::Centered(strong_handle));
+    }
+
+    /// Plays a sound effect from `pan`, -1 being all the way left and 1 all
+    /// the way right, `distance` pixels away from the listener
+    pub fn play_sfx_from(&mut self, source: Handle<AudioSource>, pan: f32, distance: f32) {
+        if !self.start_voice(&source) {
+            return;
+        }
+        // The listener sits in the middle with its ears one unit to either
+        // side, and the sound in front of it
+        let emitter = Vec3::new(pan.clamp(-1.0, 1.0), 0.0, distance / HEARING_DISTANCE);
+        let weak_handle = self.audio.play_spatial_with_settings(
+            source,
+            self.sfx_playback_settings(1.0),
+            Transform::IDENTITY,
+            2.0,
+            emitter,
+        );
+        let strong_handle = self.spatial_audio_sinks.get_handle(weak_handle);
+        self.channels.sfx.push_back(Voice::Placed(strong_handle));
+    }
+
+    /// Makes room for one more sound effect, unless the same one has only
+    /// just started
+    fn start_voice(&mut self, source: &Handle<AudioSource>) -> bool {
+        let now = self.time.elapsed_seconds();
+        if let Some(&started) = self.channels.sfx_started.get(&source.id()) {
+            if now - started < SFX_RETRIGGER_SECONDS {
+                return false;
+            }
+        }
+        self.channels.sfx_started.insert(source.id(), now);
+
+        self.channels
+            .sfx
+            .retain(|voice| !voice.finished(&self.audio_sinks, &self.spatial_audio_sinks));
+        if self.channels.sfx.len() >= MAX_SFX_VOICES {
+            if let Some(oldest) = self.channels.sfx.pop_front() {
+                oldest.stop(&self.audio_sinks, &self.spatial_audio_sinks);
+            }
+        }
+        true
+    }
+
+    fn sfx_playback_settings(&self, speed: f32) -> PlaybackSettings {
+        PlaybackSettings {
+            repeat: false,
+            volume: Volume::new_relative(self.settings.sfx_volume()),
+            speed,
+        }
+    }
+}
+
+/// Starts music that has loaded, moves the crossfades along and applies the
+/// channel volumes
+pub fn mixer_update(
+    mut channels: ResMut<Channels>,
+    music_audio: Res<Audio<MusicSource>>,
+    mut music_sources: ResMut<Assets<MusicSource>>,
+    audio_sources: Res<Assets<AudioSource>>,
+    audio_sinks: Res<Assets<AudioSink>>,
+    spatial_audio_sinks: Res<Assets<SpatialAudioSink>>,
+    settings: Res<Settings>,
+    time: Res<Time>,
+) {
+    for track in &mut channels.music {
+        if track.sink.is_some() || track.fading_out {
+            continue;
+        }
+        let Some(audio) = audio_sources.get(&track.source) else {
+            continue;
+        };
+        let source = music_sources.add(MusicSource {
+            audio: audio.clone(),
+            position: track.position.clone(),
+        });
+        let weak_handle = music_audio.play_with_settings(
+            source,
+            PlaybackSettings {
+                repeat: false,
+                volume: Volume::new_relative(0.0),
+                speed: 1.0,
+            },
+        );
+        track.sink = Some(audio_sinks.get_handle(weak_handle));
+    }
+
+    let delta = if channels.music_paused {
+        0.0
+    } else {
+        time.delta_seconds()
+    };
+    let step = delta / CROSSFADE_SECONDS;
+    let paused = channels.music_paused;
+
+    channels.music.retain_mut(|track| {
+        let Some(sink) = track.sink.as_ref().and_then(|sink| audio_sinks.get(sink)) else {
+            // Not started yet, and no need to once it's been replaced
+            return track.sink.is_some() || !track.fading_out;
+        };
+        if paused {
+            // Also holds a track whose sink only appeared while paused
+            sink.pause();
+        }
+        track.gain = if track.fading_out {
+            (track.gain - step).max(0.0)
+        } else {
+            (track.gain + step).min(1.0)
+        };
+        if track.fading_out && track.gain == 0.0 {
+            sink.stop();
+            return false;
+        }
+        sink.set_volume(track.gain * settings.music_volume());
+        true
+    });
+
+    if settings.is_changed() {
+        for voice in &channels.sfx {
+            voice.set_volume(&audio_sinks, &spatial_audio_sinks, settings.sfx_volume());
+        }
+    }
+}
@@ -0,0 +1,600 @@
+use std::collections::VecDeque;
+
+use bevy::prelude::*;
+
+use crate::candy::CandyKind;
+use crate::collision::{Collider, SpatialIndex};
+use crate::config::GameConfig;
+use crate::input::{GameInput, MAX_PLAYERS};
+use crate::protocol::{self, ClientMessage, PlayerInput, ServerMessage, TickFrame, PLAYERS};
+use crate::timestep::SimulationTime;
+use crate::{
+    Arena, Candy, CandyImage, CandySerials, GameSound, GameState, Player, PlayerCount, Score, Text,
+};
+
+/// Input is sent this many fixed updates ahead of the simulation, which
+/// hides the round trip to the relay server
+const INPUT_DELAY_TICKS: u32 = 6;
+
+pub enum SocketEvent {
+    Message(String),
+    Closed(String),
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+mod socket {
+    use std::io::ErrorKind;
+    use std::sync::mpsc::{channel, Receiver, Sender};
+    use std::thread;
+    use std::time::Duration;
+
+    use tungstenite::stream::MaybeTlsStream;
+    use tungstenite::Message;
+
+    use super::SocketEvent;
+
+    const POLL_INTERVAL: Duration = Duration::from_millis(2);
+
+    /// WebSocket running on its own thread
+    pub struct Socket {
+        outgoing: Sender<String>,
+        incoming: Receiver<SocketEvent>,
+    }
+
+    impl Socket {
+        pub fn open(url: &str) -> Socket {
+            let (outgoing, outgoing_receiver) = channel::<String>();
+            let (incoming_sender, incoming) = channel();
+            let url = url.to_string();
+
+            thread::spawn(move || {
+                let reason = match run(&url, &outgoing_receiver, &incoming_sender) {
+                    Ok(()) => "closed by the server".to_string(),
+                    Err(e) => e,
+                };
+                let _ = incoming_sender.send(SocketEvent::Closed(reason));
+            });
+
+            Socket { outgoing, incoming }
+        }
+
+        pub fn send(&mut self, text: String) {
+            // When the thread is gone, a Closed event says why
+            let _ = self.outgoing.send(text);
+        }
+
+        pub fn poll(&mut self) -> Vec<SocketEvent> {
+            self.incoming.try_iter().collect()
+        }
+    }
+
+    fn run(
+        url: &str,
+        outgoing: &Receiver<String>,
+        incoming: &Sender<SocketEvent>,
+    ) -> Result<(), String> {
+        let (mut socket, _) = tungstenite::connect(url).map_err(|e| e.to_string())?;
+        if let MaybeTlsStream::Plain(stream) = socket.get_ref() {
+            stream
+                .set_read_timeout(Some(POLL_INTERVAL))
+                .map_err(|e| e.to_string())?;
+        }
+
+        loop {
+            match socket.read() {
+                Ok(Message::Text(text)) => {
+                    if incoming.send(SocketEvent::Message(text)).is_err() {
+                        return Ok(());
+                    }
+                }
+                Ok(Message::Close(_)) => return Ok(()),
+                Ok(_) => {}
+                Err(tungstenite::Error::Io(e))
+                    if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {}
+                Err(e) => return Err(e.to_string()),
+            }
+
+            while let Ok(text) = outgoing.try_recv() {
+                socket
+                    .send(Message::Text(text))
+                    .map_err(|e| e.to_string())?;
+            }
+        }
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+mod socket {
+    use std::cell::RefCell;
+    use std::collections::VecDeque;
+    use std::rc::Rc;
+
+    use wasm_bindgen::closure::Closure;
+    use wasm_bindgen::JsCast;
+    use web_sys::{CloseEvent, MessageEvent, WebSocket};
+
+    use super::SocketEvent;
+
+    /// The browser's WebSocket, which hands messages to callbacks on the
+    /// main thread
+    pub struct Socket {
+        socket: Option<WebSocket>,
+        pending: Vec<String>,
+        incoming: Rc<RefCell<VecDeque<SocketEvent>>>,
+        _on_message: Closure<dyn FnMut(MessageEvent)>,
+        _on_close: Closure<dyn FnMut(CloseEvent)>,
+    }
+
+    impl Socket {
+        pub fn open(url: &str) -> Socket {
+            let incoming = Rc::new(RefCell::new(VecDeque::new()));
+
+            let on_message = {
+                let incoming = incoming.clone();
+                Closure::<dyn FnMut(MessageEvent)>::new(move |event: MessageEvent| {
+                    if let Some(text) = event.data().as_string() {
+                        incoming.borrow_mut().push_back(SocketEvent::Message(text));
+                    }
+                })
+            };
+            let on_close = {
+                let incoming = incoming.clone();
+                Closure::<dyn FnMut(CloseEvent)>::new(move |event: CloseEvent| {
+                    let reason = if event.reason().is_empty() {
+                        format!("closed with code {}", event.code())
+                    } else {
+                        event.reason()
+                    };
+                    incoming.borrow_mut().push_back(SocketEvent::Closed(reason));
+                })
+            };
+
+            let socket = match WebSocket::new(url) {
+                Ok(socket) => {
+                    socket.set_onmessage(Some(on_message.as_ref().unchecked_ref()));
+                    socket.set_onclose(Some(on_close.as_ref().unchecked_ref()));
+                    Some(socket)
+                }
+                Err(e) => {
+                    incoming
+                        .borrow_mut()
+                        .push_back(SocketEvent::Closed(format!("{e:?}")));
+                    None
+                }
+            };
+
+            Socket {
+                socket,
+                pending: Vec::new(),
+                incoming,
+                _on_message: on_message,
+                _on_close: on_close,
+            }
+        }
+
+        pub fn send(&mut self, text: String) {
+            self.pending.push(text);
+            self.flush();
+        }
+
+        pub fn poll(&mut self) -> Vec<SocketEvent> {
+            self.flush();
+            self.incoming.borrow_mut().drain(..).collect()
+        }
+
+        /// Messages can only be sent once the connection is open
+        fn flush(&mut self) {
+            let Some(socket) = &self.socket else {
+                return;
+            };
+            if socket.ready_state() != WebSocket::OPEN {
+                return;
+            }
+            for text in self.pending.drain(..) {
+                let _ = socket.send_with_str(&text);
+            }
+        }
+    }
+}
+
+/// The connection to the relay server. Not a regular resource because the
+/// browser's WebSocket only lives on the main thread.
+pub struct Connection {
+    url: String,
+    socket: socket::Socket,
+    closed: bool,
+    /// Joined the server's queue and waiting for an opponent
+    waiting: bool,
+}
+
+impl Connection {
+    pub fn open(url: &str) -> Self {
+        info!("connecting to relay server {url}");
+        Connection {
+            url: url.to_string(),
+            socket: socket::Socket::open(url),
+            closed: false,
+            waiting: false,
+        }
+    }
+
+    fn send(&mut self, message: &ClientMessage) {
+        if !self.closed {
+            self.socket.send(protocol::encode(message));
+        }
+    }
+}
+
+/// An online round in progress. Its simulation only advances on tick frames
+/// from the relay server, and the server decides which candy spawns and
+/// who gets to eat it.
+#[derive(Resource)]
+pub struct NetworkSession {
+    pub local_player: usize,
+    next_tick: u32,
+    sent_tick: u32,
+    frames: VecDeque<TickFrame>,
+}
+
+/// Candy as known to the relay server
+#[derive(Component)]
+pub struct NetCandy {
+    id: u32,
+    /// Our caticorn touched it and we asked the server for it
+    claimed: bool,
+}
+
+#[derive(Component)]
+pub struct WaitingText {}
+
+/// Run condition for everything the relay server is in charge of online
+pub fn offline(session: Option<Res<NetworkSession>>) -> bool {
+    session.is_none()
+}
+
+/// Run condition holding the simulation until the next tick frame is in
+pub fn frame_ready(session: Option<Res<NetworkSession>>) -> bool {
+    match session {
+        Some(session) => !session.frames.is_empty(),
+        None => true,
+    }
+}
+
+fn player_input(input: &GameInput) -> PlayerInput {
+    let bytes = input.to_bytes();
+    PlayerInput {
+        x: bytes[0] as i8,
+        y: bytes[1] as i8,
+        flags: bytes[4],
+    }
+}
+
+fn game_input(inputs: &[PlayerInput; PLAYERS]) -> GameInput {
+    let [first, second] = inputs;
+    GameInput::from_bytes([
+        first.x as u8,
+        first.y as u8,
+        second.x as u8,
+        second.y as u8,
+        first.flags | second.flags,
+    ])
+}
+
+pub fn net_join(
+    mut commands: Commands,
+    mut connection: NonSendMut<Connection>,
+    asset_server: Res<AssetServer>,
+) {
+    commands.remove_resource::<NetworkSession>();
+    if connection.closed {
+        return;
+    }
+
+    connection.send(&ClientMessage::Join);
+    connection.waiting = true;
+
+    commands.spawn((
+        TextBundle::from_section(
+            format!("waiting for another caticorn on {}", connection.url),
+            TextStyle {
+                font: asset_server.load("fonts/MesloLGS NF Regular.ttf"),
+                font_size: 20.0,
+                color: Color::WHITE,
+            },
+        )
+        .with_text_alignment(TextAlignment::Left)
+        .with_style(Style {
+            position_type: PositionType::Absolute,
+            top: Val::Px(5.0),
+            right: Val::Px(15.0),
+            ..default()
+        }),
+        Text {},
+        WaitingText {},
+    ));
+}
+
+/// Starting a round on our own takes us out of the queue
+pub fn net_leave_queue(mut connection: NonSendMut<Connection>) {
+    if connection.waiting {
+        connection.send(&ClientMessage::Leave);
+        connection.waiting = false;
+    }
+}
+
+pub fn net_receive(
+    mut commands: Commands,
+    mut connection: NonSendMut<Connection>,
+    mut session: Option<ResMut<NetworkSession>>,
+    mut player_count: ResMut<PlayerCount>,
+    mut arena: ResMut<Arena>,
+    mut next_state: ResMut<NextState<GameState>>,
+    mut waiting_text_query: Query<&mut bevy::text::Text, With<WaitingText>>,
+) {
+    for event in connection.socket.poll() {
+        let text = match event {
+            SocketEvent::Message(text) => text,
+            SocketEvent::Closed(reason) => {
+                error!("lost connection to the relay server: {reason}");
+                connection.closed = true;
+                connection.waiting = false;
+                for mut waiting_text in &mut waiting_text_query {
+                    waiting_text.sections[0].value = format!("relay server offline: {reason}");
+                }
+                if session.is_some() {
+                    next_state.set(GameState::Title);
+                }
+                continue;
+            }
+        };
+
+        let message = match protocol::decode::<ServerMessage>(&text) {
+            Ok(message) => message,
+            Err(e) => {
+                warn!("ignoring bad message from the relay server: {e}");
+                continue;
+            }
+        };
+
+        match message {
+            ServerMessage::Start { player } => {
+                if !connection.waiting {
+                    // Matched just as we started a round on our own
+                    connection.send(&ClientMessage::Leave);
+                    continue;
+                }
+                info!("online round starting, playing as player {}", player + 1);
+                connection.waiting = false;
+                commands.insert_resource(NetworkSession {
+                    local_player: (player as usize).min(MAX_PLAYERS - 1),
+                    next_tick: 0,
+                    sent_tick: 0,
+                    frames: VecDeque::new(),
+                });
+                **player_count = PLAYERS;
+                arena.width = protocol::ARENA_WIDTH;
+                arena.height = protocol::ARENA_HEIGHT;
+                next_state.set(GameState::Playing);
+            }
+            ServerMessage::Tick(frame) => {
+                if let Some(session) = session.as_mut() {
+                    session.frames.push_back(frame);
+                }
+            }
+            ServerMessage::OpponentLeft => {
+                if session.is_some() {
+                    warn!("the other caticorn left the round");
+                    next_state.set(GameState::Title);
+                }
+            }
+        }
+    }
+}
+
+/// Sends our input for the ticks up to INPUT_DELAY_TICKS ahead of the
+/// simulation
+pub fn net_send_input(
+    mut connection: NonSendMut<Connection>,
+    session: Option<ResMut<NetworkSession>>,
+    input: Res<GameInput>,
+) {
+    let Some(mut session) = session else {
+        return;
+    };
+    let input = player_input(&input);
+    while session.sent_tick < session.next_tick + INPUT_DELAY_TICKS {
+        let tick = session.sent_tick;
+        connection.send(&ClientMessage::Input { tick, input });
+        session.sent_tick += 1;
+    }
+}
+
+/// Applies the next tick frame: everybody's input, the candy the server
+/// spawned and the candy it handed out
+pub fn net_apply_frame(
+    mut commands: Commands,
+    session: Option<ResMut<NetworkSession>>,
+    mut input: ResMut<GameInput>,
+    candy_image: Res<CandyImage>,
+    mut candy_serials: ResMut<CandySerials>,
+    candy_query: Query<(Entity, &NetCandy, &Candy)>,
+    mut player_query: Query<(&Player, &mut Transform, &mut Score)>,
+    mut sounds: EventWriter<GameSound>,
+    time: Res<SimulationTime>,
+    config: Res<GameConfig>,
+) {
+    let Some(mut session) = session else {
+        return;
+    };
+    let Some(frame) = session.frames.pop_front() else {
+        return;
+    };
+    if frame.tick != session.next_tick {
+        warn!(
+            "expected tick {} from the relay server, got {}",
+            session.next_tick, frame.tick
+        );
+    }
+    session.next_tick = frame.tick + 1;
+
+    *input = game_input(&frame.inputs);
+
+    for spawn in &frame.spawns {
+        let kind = CandyKind::from_index(spawn.kind).unwrap_or_default();
+        crate::spawn_candy_at(
+            &mut commands,
+            &candy_image,
+            candy_serials.next(),
+            kind,
+            Vec3::new(spawn.x, spawn.y, 0.0),
+            Vec2::new(spawn.direction_x, spawn.direction_y),
+        )
+        .insert(NetCandy {
+            id: spawn.id,
+            claimed: false,
+        });
+    }
+
+    for eaten in &frame.eaten {
+        let Some((entity, _, candy)) = candy_query
+            .iter()
+            .find(|(_, net_candy, _)| net_candy.id == eaten.id)
+        else {
+            continue;
+        };
+        commands.entity(entity).despawn();
+        for (player, mut transform, mut score) in &mut player_query {
+            if player.index == eaten.player as usize {
+                sounds.send(GameSound::PlayerEat(transform.translation.truncate()));
+                crate::feed_player(
+                    &mut transform,
+                    &mut score,
+                    candy.kind,
+                    time.elapsed_seconds(),
+                    &config,
+                );
+            }
+        }
+    }
+}
+
+/// Asks the relay server for the candy our caticorn touches. Whether we get
+/// it arrives with a later tick frame.
+pub fn net_claim_candy(
+    mut connection: NonSendMut<Connection>,
+    session: Option<Res<NetworkSession>>,
+    player_query: Query<(&Player, &Collider, &Transform), Without<Candy>>,
+    mut candy_query: Query<(&mut NetCandy, &Collider, &Transform), Without<Player>>,
+    index: Res<SpatialIndex>,
+) {
+    let Some(session) = session else {
+        return;
+    };
+    let Some((_, player_collider, player_transform)) = player_query
+        .iter()
+        .find(|(player, _, _)| player.index == session.local_player)
+    else {
+        return;
+    };
+
+    let position = player_transform.translation.truncate();
+    let reach = player_collider.reach(player_transform);
+    for entity in index.candy.near(position, reach) {
+        let Ok((mut candy, candy_collider, candy_transform)) = candy_query.get_mut(entity) else {
+            continue;
+        };
+        if !candy.claimed
+            && player_collider.overlaps(player_transform, candy_collider, candy_transform)
+        {
+            connection.send(&ClientMessage::Eat { candy: candy.id });
+            candy.claimed = true;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use bevy::time::TimeUpdateStrategy;
+
+    use super::*;
+    use crate::powerup::{PowerUpFont, PowerUps};
+    use crate::protocol::CandySpawn;
+    use crate::timestep::{Interpolated, FIXED_TIMESTEP_SECONDS};
+    use crate::{collision, BodySize, InputSet, PlayerControl, PlayerImage, StepSet, PLAYER_SIZE};
+
+    #[derive(Resource)]
+    struct RoundEnded;
+
+    fn spawn(id: u32, x: f32) -> CandySpawn {
+        CandySpawn {
+            id,
+            kind: 0,
+            x,
+            y: 0.0,
+            direction_x: 1.0,
+            direction_y: 0.0,
+        }
+    }
+
+    #[test]
+    fn round_goes_on_after_the_first_frame_spawns_candy() {
+        let mut app = App::new();
+        app.add_plugins(MinimalPlugins)
+            .insert_resource(TimeUpdateStrategy::ManualDuration(Duration::from_secs_f32(
+                FIXED_TIMESTEP_SECONDS,
+            )))
+            .insert_resource(PlayerImage(Handle::default()))
+            .insert_resource(CandyImage(Handle::default()))
+            .insert_resource(PowerUpFont(Handle::default()));
+        crate::add_gameplay(&mut app, Some(1));
+        app.configure_set(
+            FixedUpdate,
+            StepSet.run_if(not(in_state(GameState::Playing)).or_else(frame_ready)),
+        )
+        .add_systems(
+            FixedUpdate,
+            net_apply_frame
+                .in_set(InputSet)
+                .run_if(in_state(GameState::Playing)),
+        )
+        .add_systems(OnEnter(GameState::End), |mut commands: Commands| {
+            commands.insert_resource(RoundEnded)
+        });
+
+        app.world.spawn((
+            TransformBundle::default(),
+            Player { index: 0 },
+            PlayerControl::default(),
+            BodySize(PLAYER_SIZE),
+            Collider(collision::CATICORN_SHAPE),
+            Interpolated::new(Vec3::ZERO),
+            Score::default(),
+            PowerUps::default(),
+        ));
+        **app.world.resource_mut::<PlayerCount>() = PLAYERS;
+        app.insert_resource(NetworkSession {
+            local_player: 0,
+            next_tick: 0,
+            sent_tick: 0,
+            frames: VecDeque::from([TickFrame {
+                tick: 0,
+                inputs: [PlayerInput::default(); PLAYERS],
+                spawns: vec![spawn(0, -200.0), spawn(1, 0.0), spawn(2, 200.0)],
+                eaten: Vec::new(),
+            }]),
+        });
+        app.world
+            .resource_mut::<NextState<GameState>>()
+            .set(GameState::Playing);
+
+        for _ in 0..10 {
+            app.update();
+        }
+
+        assert!(app.world.resource::<NetworkSession>().frames.is_empty());
+        assert_eq!(app.world.query::<&Candy>().iter(&app.world).count(), 3);
+        assert!(!app.world.contains_resource::<RoundEnded>());
+    }
+}
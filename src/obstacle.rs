@@ -0,0 +1,230 @@
+//! Things in the arena that caticorns and candy bump into. Each level lists
+//! its own; caticorns and candy count as circles when they hit one.
+
+use bevy::prelude::*;
+use bevy::sprite::{MaterialMesh2dBundle, Mesh2dHandle};
+use serde::{Deserialize, Serialize};
+
+use crate::levels::Levels;
+use crate::timestep::SimulationTime;
+use crate::{BodySize, Candy, GameSound, Player};
+
+/// How far a bumper throws back whatever hits it, on top of pushing it out
+const BUMPER_KICK: f32 = 12.0;
+const BAR_THICKNESS: f32 = 16.0;
+
+/// An obstacle as levels describe it, centered on (x, y)
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub enum ObstacleDef {
+    Wall {
+        x: f32,
+        y: f32,
+        width: f32,
+        height: f32,
+    },
+    /// Round, and throws back whatever hits it
+    Bumper { x: f32, y: f32, radius: f32 },
+    /// A bar turning around its middle, at `speed` radians per second
+    SpinningBar {
+        x: f32,
+        y: f32,
+        length: f32,
+        speed: f32,
+    },
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum Shape {
+    /// A rectangle, turned with the entity's rotation
+    Box {
+        half_size: Vec2,
+    },
+    Circle {
+        radius: f32,
+    },
+}
+
+#[derive(Component)]
+pub struct Obstacle {
+    pub shape: Shape,
+    /// Radians per second
+    pub spin: f32,
+    pub kick: f32,
+}
+
+impl Obstacle {
+    fn from_def(def: &ObstacleDef) -> (Obstacle, Vec2) {
+        match *def {
+            ObstacleDef::Wall {
+                x,
+                y,
+                width,
+                height,
+            } => (
+                Obstacle {
+                    shape: Shape::Box {
+                        half_size: Vec2::new(width, height) / 2.0,
+                    },
+                    spin: 0.0,
+                    kick: 0.0,
+                },
+                Vec2::new(x, y),
+            ),
+            ObstacleDef::Bumper { x, y, radius } => (
+                Obstacle {
+                    shape: Shape::Circle { radius },
+                    spin: 0.0,
+                    kick: BUMPER_KICK,
+                },
+                Vec2::new(x, y),
+            ),
+            ObstacleDef::SpinningBar {
+                x,
+                y,
+                length,
+                speed,
+            } => (
+                Obstacle {
+                    shape: Shape::Box {
+                        half_size: Vec2::new(length, BAR_THICKNESS) / 2.0,
+                    },
+                    spin: speed,
+                    kick: 0.0,
+                },
+                Vec2::new(x, y),
+            ),
+        }
+    }
+
+    fn color(&self) -> Color {
+        match (self.shape, self.spin != 0.0) {
+            (Shape::Circle { .. }, _) => Color::rgb(1.0, 0.5, 0.1),
+            (Shape::Box { .. }, true) => Color::rgb(0.8, 0.3, 0.9),
+            (Shape::Box { .. }, false) => Color::rgb(0.5, 0.5, 0.55),
+        }
+    }
+
+    /// Direction to push a circle at `point` out of the obstacle, and how
+    /// far, if they overlap
+    pub fn contact(&self, transform: &Transform, point: Vec2, radius: f32) -> Option<(Vec2, f32)> {
+        let center = transform.translation.truncate();
+        match self.shape {
+            Shape::Circle {
+                radius: obstacle_radius,
+            } => {
+                let offset = point - center;
+                let distance = offset.length();
+                let depth = obstacle_radius + radius - distance;
+                if depth <= 0.0 {
+                    return None;
+                }
+                let normal = if distance > f32::EPSILON {
+                    offset / distance
+                } else {
+                    Vec2::Y
+                };
+                Some((normal, depth))
+            }
+            Shape::Box { half_size } => {
+                let rotation = transform.rotation;
+                let local = (rotation.inverse() * (point - center).extend(0.0)).truncate();
+                let closest = local.clamp(-half_size, half_size);
+                let (normal, depth) = if closest == local {
+                    // The center is inside, leave by the nearest side
+                    let to_side = half_size - local.abs();
+                    if to_side.x < to_side.y {
+                        (Vec2::new(local.x.signum(), 0.0), to_side.x + radius)
+                    } else {
+                        (Vec2::new(0.0, local.y.signum()), to_side.y + radius)
+                    }
+                } else {
+                    let offset = local - closest;
+                    let distance = offset.length();
+                    if distance >= radius {
+                        return None;
+                    }
+                    (offset / distance, radius - distance)
+                };
+                Some(((rotation * normal.extend(0.0)).truncate(), depth))
+            }
+        }
+    }
+}
+
+pub fn gameplay_spawn_obstacles(mut commands: Commands, levels: Res<Levels>) {
+    for def in &levels.current().obstacles {
+        let (obstacle, position) = Obstacle::from_def(def);
+        commands.spawn((
+            TransformBundle::from_transform(Transform::from_translation(position.extend(0.0))),
+            obstacle,
+        ));
+    }
+}
+
+pub fn gameplay_spin_obstacles(
+    mut query: Query<(&mut Transform, &Obstacle)>,
+    time: Res<SimulationTime>,
+) {
+    for (mut transform, obstacle) in &mut query {
+        if obstacle.spin != 0.0 {
+            transform.rotate_z(obstacle.spin * time.delta_seconds());
+        }
+    }
+}
+
+/// Pushes caticorns and candy out of obstacles. Candy bounces off them like
+/// off the edges of the arena.
+pub fn gameplay_obstacle_collision(
+    obstacle_query: Query<(&Transform, &Obstacle)>,
+    mut body_query: Query<
+        (&mut Transform, &BodySize, Option<&mut Candy>),
+        (Or<(With<Player>, With<Candy>)>, Without<Obstacle>),
+    >,
+    mut sounds: EventWriter<GameSound>,
+    time: Res<SimulationTime>,
+) {
+    for (mut transform, size, mut candy) in &mut body_query {
+        let radius = size.min_element() * transform.scale.x / 2.0;
+        for (obstacle_transform, obstacle) in &obstacle_query {
+            let position = transform.translation.truncate();
+            let Some((normal, depth)) = obstacle.contact(obstacle_transform, position, radius)
+            else {
+                continue;
+            };
+            transform.translation += (normal * (depth + obstacle.kick)).extend(0.0);
+
+            if let Some(candy) = candy.as_mut() {
+                let heading = candy.direction.dot(normal);
+                if heading < 0.0 {
+                    candy.direction -= 2.0 * heading * normal;
+                    if time.elapsed_seconds() - candy.timestamp_changed_direction > 0.1 {
+                        sounds.send(GameSound::CandyBounce(transform.translation.truncate()));
+                    }
+                    candy.timestamp_changed_direction = time.elapsed_seconds();
+                }
+            }
+        }
+    }
+}
+
+/// Gives obstacles that have just been spawned something to look at
+pub fn obstacle_presentation(
+    mut commands: Commands,
+    query: Query<(Entity, &Transform, &Obstacle), Added<Obstacle>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+) {
+    for (entity, transform, obstacle) in &query {
+        let mesh = match obstacle.shape {
+            Shape::Box { half_size } => Mesh::from(shape::Quad::new(half_size * 2.0)),
+            Shape::Circle { radius } => Mesh::from(shape::Circle::new(radius)),
+        };
+        let bundle = MaterialMesh2dBundle {
+            mesh: Mesh2dHandle(meshes.add(mesh)),
+            material: materials.add(ColorMaterial::from(obstacle.color())),
+            transform: *transform,
+            ..default()
+        };
+        commands.entity(entity).insert(bundle);
+    }
+}
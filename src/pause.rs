@@ -0,0 +1,195 @@
+//! The pause menu. While it is open the simulation, and with it the candy,
+//! the timers and the countdown, holds still, and so does the music. The
+//! music plays on in the settings screen opened from here, so that changes
+//! to its volume can be heard.
+
+use bevy::prelude::*;
+
+use crate::controls::{Action, Actions};
+use crate::input::GameInput;
+use crate::mixer::Mixer;
+use crate::settings::{SettingsMenu, SettingsReturn};
+
+/// Kept apart from `GameState` so that pausing doesn't leave `Playing` and
+/// tear the round down
+#[derive(States, Default, Debug, Hash, Eq, PartialEq, Clone)]
+pub enum PauseState {
+    #[default]
+    Running,
+    Paused,
+    Settings,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum PauseOption {
+    Resume,
+    Restart,
+    Settings,
+    Quit,
+}
+
+impl PauseOption {
+    const ALL: [PauseOption; 4] = [
+        PauseOption::Resume,
+        PauseOption::Restart,
+        PauseOption::Settings,
+        PauseOption::Quit,
+    ];
+
+    fn label(self) -> &'static str {
+        match self {
+            PauseOption::Resume => "resume",
+            PauseOption::Restart => "restart round",
+            PauseOption::Settings => "settings",
+            PauseOption::Quit => "quit to title",
+        }
+    }
+}
+
+/// Index of the highlighted option in `PauseOption::ALL`
+#[derive(Resource, Default)]
+pub struct PauseMenu {
+    selected: usize,
+}
+
+impl PauseMenu {
+    /// Moves the highlight by `step`, wrapping around
+    fn move_selection(&mut self, step: isize) {
+        let count = PauseOption::ALL.len() as isize;
+        self.selected = (self.selected as isize + step).rem_euclid(count) as usize;
+    }
+
+    fn selected(&self) -> PauseOption {
+        PauseOption::ALL[self.selected]
+    }
+}
+
+#[derive(Component)]
+pub struct PauseOverlay {}
+
+#[derive(Component)]
+pub struct PauseMenuText {}
+
+/// Opens the pause menu, or leaves an online round, which can't be held for
+/// the other caticorn
+pub fn gameplay_pause(
+    actions: Actions,
+    online: Option<Res<crate::network::NetworkSession>>,
+    mut input: ResMut<GameInput>,
+    mut next_state: ResMut<NextState<PauseState>>,
+) {
+    if !actions.just_pressed(Action::Pause) {
+        return;
+    }
+    if online.is_some() {
+        input.quit = true;
+    } else {
+        next_state.set(PauseState::Paused);
+    }
+}
+
+pub fn pause_setup(mut commands: Commands, asset_server: Res<AssetServer>, mut mixer: Mixer) {
+    info!("pause_setup");
+
+    commands.insert_resource(PauseMenu::default());
+
+    mixer.pause_music();
+
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    width: Val::Percent(100.0),
+                    height: Val::Percent(100.0),
+                    justify_content: JustifyContent::Center,
+                    align_items: AlignItems::Center,
+                    ..default()
+                },
+                background_color: Color::rgba(0.0, 0.0, 0.0, 0.6).into(),
+                ..default()
+            },
+            PauseOverlay {},
+        ))
+        .with_children(|parent| {
+            let style = TextStyle {
+                font: asset_server.load("fonts/MesloLGS NF Regular.ttf"),
+                font_size: 30.0,
+                color: Color::WHITE,
+            };
+            parent.spawn((
+                TextBundle::from_sections(
+                    std::iter::once(TextSection::new("paused\n\n", style.clone())).chain(
+                        PauseOption::ALL
+                            .iter()
+                            .map(|_| TextSection::new("", style.clone())),
+                    ),
+                )
+                .with_text_alignment(TextAlignment::Left),
+                PauseMenuText {},
+            ));
+        });
+}
+
+pub fn pause_menu_input(
+    mut commands: Commands,
+    actions: Actions,
+    keyboard_input: Res<Input<KeyCode>>,
+    mut menu: ResMut<PauseMenu>,
+    mut input: ResMut<GameInput>,
+    mut text_query: Query<&mut bevy::text::Text, With<PauseMenuText>>,
+    mut next_state: ResMut<NextState<PauseState>>,
+) {
+    if actions.just_pressed(Action::MoveUp) {
+        menu.move_selection(-1);
+    }
+    if actions.just_pressed(Action::MoveDown) {
+        menu.move_selection(1);
+    }
+
+    if actions.just_pressed(Action::Pause) {
+        next_state.set(PauseState::Running);
+    } else if actions.just_pressed(Action::Start) || keyboard_input.just_pressed(KeyCode::Return) {
+        // Restarting and quitting go through the simulation, like any other
+        // input, so that they end up in the replay
+        match menu.selected() {
+            PauseOption::Resume => {}
+            PauseOption::Restart => input.restart = true,
+            PauseOption::Settings => {
+                commands.insert_resource(SettingsMenu::new(SettingsReturn::Pause));
+                next_state.set(PauseState::Settings);
+                return;
+            }
+            PauseOption::Quit => input.quit = true,
+        }
+        next_state.set(PauseState::Running);
+    }
+
+    if let Ok(mut text) = text_query.get_single_mut() {
+        for (index, option) in PauseOption::ALL.iter().enumerate() {
+            let section = &mut text.sections[index + 1];
+            let selected = index == menu.selected;
+            section.value = format!("{} {}\n", if selected { ">" } else { " " }, option.label());
+            section.style.color = if selected {
+                Color::YELLOW
+            } else {
+                Color::WHITE
+            };
+        }
+    }
+}
+
+pub fn pause_teardown(
+    mut commands: Commands,
+    entities: Query<Entity, With<PauseOverlay>>,
+    mut mixer: Mixer,
+) {
+    info!("pause_teardown");
+
+    for entity in &entities {
+        commands.entity(entity).despawn_recursive();
+    }
+    commands.remove_resource::<PauseMenu>();
+
+    mixer.resume_music();
+}
@@ -0,0 +1,248 @@
+//! Pickups that change the round for a while. They turn up like candy, and
+//! the caticorn that touches one gets its effect.
+
+use std::collections::BTreeMap;
+
+use bevy::prelude::*;
+use rand::Rng;
+
+use crate::collision::{self, Collider};
+use crate::rng::GameRng;
+use crate::timestep::SimulationTime;
+use crate::{Arena, BodySize, GameSound, Player, PlayerCount};
+
+pub const POWER_UP_SPAWN_SECONDS: f32 = 10.0;
+/// How long a pickup waits for a caticorn before it vanishes
+pub const POWER_UP_LIFETIME_SECONDS: f32 = 8.0;
+pub const POWER_UP_SIZE: Vec2 = Vec2::new(48.0, 48.0);
+pub const SPEED_BOOST_FACTOR: f32 = 1.6;
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum PowerUpKind {
+    /// Candy is drawn to the caticorn instead of running from it
+    Magnet,
+    SpeedBoost,
+    /// No candy moves
+    Freeze,
+    /// The caticorn is back to its original size
+    SlimDown,
+}
+
+impl PowerUpKind {
+    pub const ALL: [PowerUpKind; 4] = [
+        PowerUpKind::Magnet,
+        PowerUpKind::SpeedBoost,
+        PowerUpKind::Freeze,
+        PowerUpKind::SlimDown,
+    ];
+
+    /// Seconds the effect lasts, zero for the ones that happen at once
+    pub fn duration(self) -> f32 {
+        match self {
+            PowerUpKind::Magnet => 6.0,
+            PowerUpKind::SpeedBoost => 6.0,
+            PowerUpKind::Freeze => 4.0,
+            PowerUpKind::SlimDown => 0.0,
+        }
+    }
+
+    /// Glyph of the Nerd Font the game uses
+    pub fn icon(self) -> &'static str {
+        match self {
+            PowerUpKind::Magnet => "\u{f076}",
+            PowerUpKind::SpeedBoost => "\u{f0e7}",
+            PowerUpKind::Freeze => "\u{f2dc}",
+            PowerUpKind::SlimDown => "\u{f066}",
+        }
+    }
+
+    pub fn color(self) -> Color {
+        match self {
+            PowerUpKind::Magnet => Color::rgb(1.0, 0.3, 0.3),
+            PowerUpKind::SpeedBoost => Color::rgb(1.0, 0.9, 0.2),
+            PowerUpKind::Freeze => Color::rgb(0.5, 0.8, 1.0),
+            PowerUpKind::SlimDown => Color::rgb(0.6, 1.0, 0.5),
+        }
+    }
+}
+
+/// A pickup lying in the arena
+#[derive(Component)]
+pub struct PowerUp {
+    pub kind: PowerUpKind,
+    remaining: f32,
+}
+
+/// Effects a caticorn has picked up, with the seconds left on each
+#[derive(Component, Default)]
+pub struct PowerUps {
+    remaining: BTreeMap<PowerUpKind, f32>,
+}
+
+impl PowerUps {
+    pub fn is_active(&self, kind: PowerUpKind) -> bool {
+        self.remaining.contains_key(&kind)
+    }
+
+    pub fn active(&self) -> impl Iterator<Item = (PowerUpKind, f32)> + '_ {
+        self.remaining
+            .iter()
+            .map(|(&kind, &remaining)| (kind, remaining))
+    }
+
+    /// Picking up an effect that is still active starts it over
+    fn activate(&mut self, kind: PowerUpKind) {
+        if kind.duration() > 0.0 {
+            self.remaining.insert(kind, kind.duration());
+        }
+    }
+
+    fn tick(&mut self, seconds: f32) {
+        for remaining in self.remaining.values_mut() {
+            *remaining -= seconds;
+        }
+        self.remaining.retain(|_, remaining| *remaining > 0.0);
+    }
+}
+
+#[derive(Resource, Deref, DerefMut)]
+pub struct PowerUpSpawnTimer(pub Timer);
+
+/// Font the pickups and their HUD icons are drawn with
+#[derive(Resource)]
+pub struct PowerUpFont(pub Handle<Font>);
+
+#[derive(Component)]
+pub struct PowerUpText {}
+
+pub fn gameplay_power_up_setup(
+    mut timer: ResMut<PowerUpSpawnTimer>,
+    mut power_ups_query: Query<&mut PowerUps>,
+) {
+    timer.reset();
+    for mut power_ups in &mut power_ups_query {
+        *power_ups = PowerUps::default();
+    }
+}
+
+/// Puts a random pickup somewhere in the arena now and then, one at a time
+pub fn gameplay_spawn_power_up(
+    mut commands: Commands,
+    query: Query<(), With<PowerUp>>,
+    time: Res<SimulationTime>,
+    mut timer: ResMut<PowerUpSpawnTimer>,
+    arena: Res<Arena>,
+    font: Res<PowerUpFont>,
+    mut rng: ResMut<GameRng>,
+) {
+    timer.tick(time.delta());
+    if !timer.just_finished() || !query.is_empty() {
+        return;
+    }
+
+    let rng = &mut rng.gameplay;
+    let kind = PowerUpKind::ALL[rng.gen_range(0..PowerUpKind::ALL.len())];
+    let width = arena.width - POWER_UP_SIZE.x;
+    let height = arena.height - POWER_UP_SIZE.y;
+    let translation = Vec3::new(
+        rng.gen::<f32>() * width - width / 2.0,
+        rng.gen::<f32>() * height - height / 2.0,
+        0.0,
+    );
+
+    commands.spawn((
+        Text2dBundle {
+            text: bevy::text::Text::from_section(
+                kind.icon(),
+                TextStyle {
+                    font: font.0.clone(),
+                    font_size: POWER_UP_SIZE.y,
+                    color: kind.color(),
+                },
+            )
+            .with_alignment(TextAlignment::Center),
+            transform: Transform::from_translation(translation),
+            ..default()
+        },
+        PowerUp {
+            kind,
+            remaining: POWER_UP_LIFETIME_SECONDS,
+        },
+        BodySize(POWER_UP_SIZE),
+        Collider(collision::POWER_UP_SHAPE),
+    ));
+}
+
+pub fn gameplay_power_up_pickup(
+    mut commands: Commands,
+    mut player_query: Query<(&Player, &Collider, &mut Transform, &mut PowerUps), Without<PowerUp>>,
+    pickup_query: Query<(Entity, &PowerUp, &Collider, &Transform), Without<Player>>,
+    mut sounds: EventWriter<GameSound>,
+) {
+    // The first caticorn gets the pickup when two reach it together, as with
+    // candy
+    let mut players: Vec<_> = player_query.iter_mut().collect();
+    players.sort_by_key(|(player, ..)| player.index);
+
+    for (pickup_entity, pickup, pickup_collider, pickup_transform) in &pickup_query {
+        for (_, player_collider, player_transform, power_ups) in &mut players {
+            if player_collider.overlaps(player_transform, pickup_collider, pickup_transform) {
+                sounds.send(GameSound::PowerUp);
+                commands.entity(pickup_entity).despawn();
+                if pickup.kind == PowerUpKind::SlimDown {
+                    player_transform.scale.x = 1.0;
+                    player_transform.scale.y = 1.0;
+                }
+                power_ups.activate(pickup.kind);
+                break;
+            }
+        }
+    }
+}
+
+/// Runs down active effects, and pickups nobody went for
+pub fn gameplay_tick_power_ups(
+    mut commands: Commands,
+    mut power_ups_query: Query<&mut PowerUps>,
+    mut pickup_query: Query<(Entity, &mut PowerUp)>,
+    time: Res<SimulationTime>,
+) {
+    for mut power_ups in &mut power_ups_query {
+        power_ups.tick(time.delta_seconds());
+    }
+    for (entity, mut pickup) in &mut pickup_query {
+        pickup.remaining -= time.delta_seconds();
+        if pickup.remaining <= 0.0 {
+            commands.entity(entity).despawn();
+        }
+    }
+}
+
+pub fn gameplay_update_power_up_text(
+    mut text_query: Query<&mut bevy::text::Text, With<PowerUpText>>,
+    player_query: Query<(&Player, &PowerUps)>,
+    player_count: Res<PlayerCount>,
+) {
+    let Ok(mut text) = text_query.get_single_mut() else {
+        return;
+    };
+    let mut players: Vec<_> = player_query.iter().collect();
+    players.sort_by_key(|(player, _)| player.index);
+
+    let mut lines = Vec::new();
+    for (player, power_ups) in players {
+        for (kind, remaining) in power_ups.active() {
+            if **player_count > 1 {
+                lines.push(format!(
+                    "P{} {} {:.1}",
+                    player.index + 1,
+                    kind.icon(),
+                    remaining
+                ));
+            } else {
+                lines.push(format!("{} {:.1}", kind.icon(), remaining));
+            }
+        }
+    }
+    text.sections[0].value = lines.join("\n");
+}
@@ -0,0 +1,111 @@
+//! Messages between the game and the relay server, sent as RON in WebSocket
+//! text frames. Compiled into both the game and `src/bin/relay.rs`, so it
+//! must not depend on bevy.
+
+use rand::Rng;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+/// Caticorns in an online round
+pub const PLAYERS: usize = 2;
+/// Online rounds use a fixed playfield, whatever size the windows are
+pub const ARENA_WIDTH: f32 = 800.0;
+pub const ARENA_HEIGHT: f32 = 600.0;
+// Online rounds are played on the built-in level, `Level::default()` in
+// src/levels.rs, whatever the level set says. It takes these from here.
+pub const INITIAL_CANDIES: usize = 3;
+/// The candy spawn timer of the built-in level, in fixed updates
+pub const CANDY_SPAWN_INTERVAL_TICKS: u32 = 40;
+pub const MAX_CANDY: usize = 100;
+/// Chance of each kind of candy (donut, sprinkles, licorice, gumdrop,
+/// rotten) to be spawned. Gumdrops are left out, the halves they split into
+/// would need ids from the relay server.
+pub const CANDY_KIND_WEIGHTS: [u32; 5] = [6, 2, 2, 0, 1];
+
+/// Index of one of `weights`, each with a chance in proportion to its
+/// weight, or nothing if they're all zero. The game and the relay server
+/// both pick candy kinds with this.
+pub fn pick_weighted<I>(weights: I, rng: &mut impl Rng) -> Option<usize>
+where
+    I: IntoIterator<Item = u32>,
+    I::IntoIter: Clone,
+{
+    let weights = weights.into_iter();
+    let total: u32 = weights.clone().sum();
+    if total == 0 {
+        return None;
+    }
+    let mut pick = rng.gen_range(0..total);
+    for (index, weight) in weights.enumerate() {
+        if pick < weight {
+            return Some(index);
+        }
+        pick -= weight;
+    }
+    None
+}
+
+/// One player's input for one fixed update, as encoded in replays
+#[derive(Serialize, Deserialize, Clone, Copy, Default, Debug)]
+pub struct PlayerInput {
+    pub x: i8,
+    pub y: i8,
+    pub flags: u8,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct CandySpawn {
+    pub id: u32,
+    /// Index into CANDY_KIND_WEIGHTS
+    pub kind: u8,
+    pub x: f32,
+    pub y: f32,
+    pub direction_x: f32,
+    pub direction_y: f32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct CandyEaten {
+    pub id: u32,
+    pub player: u8,
+}
+
+/// Everything both clients need to simulate one fixed update of a round
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct TickFrame {
+    pub tick: u32,
+    pub inputs: [PlayerInput; PLAYERS],
+    pub spawns: Vec<CandySpawn>,
+    pub eaten: Vec<CandyEaten>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub enum ClientMessage {
+    /// Wait for an opponent, and start a round when there is one
+    Join,
+    /// Stop waiting, or give up the current round
+    Leave,
+    Input {
+        tick: u32,
+        input: PlayerInput,
+    },
+    /// The sender's caticorn touched this candy
+    Eat {
+        candy: u32,
+    },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub enum ServerMessage {
+    Start { player: u8 },
+    Tick(TickFrame),
+    OpponentLeft,
+}
+
+pub fn encode<T: Serialize>(message: &T) -> String {
+    ron::to_string(message).expect("protocol messages always serialize")
+}
+
+pub fn decode<T: DeserializeOwned>(text: &str) -> Result<T, String> {
+    ron::from_str(text).map_err(|e| e.to_string())
+}
@@ -0,0 +1,403 @@
+use std::path::Path;
+
+use bevy::app::AppExit;
+use bevy::prelude::*;
+
+use crate::config::GameConfig;
+use crate::input::{GameInput, MAX_PLAYERS};
+use crate::levels::{Level, Levels};
+use crate::{storage, Arena, PlayerCount, RoundStats, Score};
+
+const MAGIC: &[u8] = b"CATREPLAY";
+const VERSION: u8 = 10;
+const SCORE_SIZE: usize = 5 * 4;
+const FRAME_SIZE: usize = 2 + 5;
+const LAST_ROUND_KEY: &str = "last_round.replay";
+
+/// A recorded round: its RNG seed, how many caticorns took part, which
+/// level they played and what it was like, the scores they brought from
+/// earlier levels, the size of the arena and the config they played with,
+/// and the input of every fixed update the simulation ran
+pub struct Replay {
+    pub seed: u64,
+    pub players: u8,
+    /// Index of the level in the level set
+    pub level: u8,
+    pub level_def: Level,
+    /// One per caticorn, with the time of the last eat counted from the
+    /// start of the round
+    pub scores: Vec<Score>,
+    pub arena_width: f32,
+    pub arena_height: f32,
+    pub config: GameConfig,
+    pub frames: Vec<GameInput>,
+}
+
+impl Replay {
+    /// Header, with the level and the config as RON, then runs of identical
+    /// frames as (run length, input)
+    pub fn encode(&self) -> Vec<u8> {
+        let level_def = ron::to_string(&self.level_def).expect("levels always serialize");
+        let config = ron::to_string(&self.config).expect("the config always serializes");
+        let mut bytes = Vec::with_capacity(
+            MAGIC.len()
+                + 27
+                + self.scores.len() * SCORE_SIZE
+                + level_def.len()
+                + config.len()
+                + self.frames.len() * FRAME_SIZE,
+        );
+        bytes.extend_from_slice(MAGIC);
+        bytes.push(VERSION);
+        bytes.extend_from_slice(&self.seed.to_le_bytes());
+        bytes.push(self.players);
+        bytes.push(self.level);
+        for score in &self.scores {
+            bytes.extend_from_slice(&score.points.to_le_bytes());
+            bytes.extend_from_slice(&score.candies_eaten.to_le_bytes());
+            bytes.extend_from_slice(&score.combo.to_le_bytes());
+            bytes.extend_from_slice(&score.best_combo.to_le_bytes());
+            bytes.extend_from_slice(&score.last_eat_time.to_le_bytes());
+        }
+        bytes.extend_from_slice(&self.arena_width.to_le_bytes());
+        bytes.extend_from_slice(&self.arena_height.to_le_bytes());
+        bytes.extend_from_slice(&(level_def.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(level_def.as_bytes());
+        bytes.extend_from_slice(&(config.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(config.as_bytes());
+
+        let mut frames = self.frames.iter().peekable();
+        while let Some(frame) = frames.next() {
+            let mut run: u16 = 1;
+            while run < u16::MAX && frames.peek() == Some(&frame) {
+                frames.next();
+                run += 1;
+            }
+            bytes.extend_from_slice(&run.to_le_bytes());
+            bytes.extend_from_slice(&frame.to_bytes());
+        }
+
+        bytes
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Replay, String> {
+        let Some(bytes) = bytes.strip_prefix(MAGIC) else {
+            return Err("not a caticorn replay".to_string());
+        };
+        let (&version, bytes) = bytes.split_first().ok_or("truncated header")?;
+        if version != VERSION {
+            return Err(format!("unsupported replay version {version}"));
+        }
+        if bytes.len() < 22 {
+            return Err("truncated header".to_string());
+        }
+        let (seed, bytes) = bytes.split_at(8);
+        let seed = u64::from_le_bytes(seed.try_into().unwrap());
+        let (&players, bytes) = bytes.split_first().unwrap();
+        let (&level, mut bytes) = bytes.split_first().unwrap();
+        if bytes.len() < players as usize * SCORE_SIZE + 16 {
+            return Err("truncated header".to_string());
+        }
+        let mut scores = Vec::with_capacity(players as usize);
+        for _ in 0..players {
+            let (record, rest) = bytes.split_at(SCORE_SIZE);
+            let field = |index: usize| -> [u8; 4] { record[index * 4..][..4].try_into().unwrap() };
+            scores.push(Score {
+                points: u32::from_le_bytes(field(0)),
+                candies_eaten: u32::from_le_bytes(field(1)),
+                combo: u32::from_le_bytes(field(2)),
+                best_combo: u32::from_le_bytes(field(3)),
+                last_eat_time: f32::from_le_bytes(field(4)),
+            });
+            bytes = rest;
+        }
+        let (arena_width, bytes) = bytes.split_at(4);
+        let arena_width = f32::from_le_bytes(arena_width.try_into().unwrap());
+        let (arena_height, bytes) = bytes.split_at(4);
+        let arena_height = f32::from_le_bytes(arena_height.try_into().unwrap());
+        let (level_def_len, bytes) = bytes.split_at(4);
+        let level_def_len = u32::from_le_bytes(level_def_len.try_into().unwrap()) as usize;
+        if bytes.len() < level_def_len + 4 {
+            return Err("truncated header".to_string());
+        }
+        let (level_def, bytes) = bytes.split_at(level_def_len);
+        let level_def = ron::de::from_bytes::<Level>(level_def)
+            .map_err(|e| format!("broken level in header: {e}"))?;
+        let (config_len, bytes) = bytes.split_at(4);
+        let config_len = u32::from_le_bytes(config_len.try_into().unwrap()) as usize;
+        if bytes.len() < config_len {
+            return Err("truncated header".to_string());
+        }
+        let (config, mut bytes) = bytes.split_at(config_len);
+        let config = ron::de::from_bytes::<GameConfig>(config)
+            .map_err(|e| format!("broken config in header: {e}"))?;
+
+        let mut frames = Vec::new();
+        while !bytes.is_empty() {
+            if bytes.len() < FRAME_SIZE {
+                return Err("truncated frame".to_string());
+            }
+            let (record, rest) = bytes.split_at(FRAME_SIZE);
+            let run = u16::from_le_bytes([record[0], record[1]]);
+            let frame = GameInput::from_bytes(record[2..FRAME_SIZE].try_into().unwrap());
+            frames.extend(std::iter::repeat(frame).take(run as usize));
+            bytes = rest;
+        }
+
+        Ok(Replay {
+            seed,
+            players,
+            level,
+            level_def,
+            scores,
+            arena_width,
+            arena_height,
+            config,
+            frames,
+        })
+    }
+
+    pub fn load(path: &Path) -> Result<Replay, String> {
+        let bytes = std::fs::read(path).map_err(|e| format!("{}: {e}", path.display()))?;
+        Replay::decode(&bytes).map_err(|e| format!("{}: {e}", path.display()))
+    }
+}
+
+#[derive(Resource)]
+pub struct ReplayRecorder(Replay);
+
+#[derive(Resource)]
+pub struct ReplayPlayback {
+    replay: Replay,
+    next_frame: usize,
+}
+
+impl ReplayPlayback {
+    pub fn new(replay: Replay) -> Self {
+        ReplayPlayback {
+            replay,
+            next_frame: 0,
+        }
+    }
+
+    pub fn config(&self) -> &GameConfig {
+        &self.replay.config
+    }
+
+    pub fn level_index(&self) -> usize {
+        self.replay.level as usize
+    }
+
+    pub fn level(&self) -> &Level {
+        &self.replay.level_def
+    }
+
+    /// The score a caticorn brought into the recorded round, for a round
+    /// starting `now`
+    pub fn score_at_start(&self, index: usize, now: f32) -> Score {
+        let mut score = self.replay.scores.get(index).cloned().unwrap_or_default();
+        score.last_eat_time += now;
+        score
+    }
+}
+
+pub fn replay_start_recording(
+    mut commands: Commands,
+    round_stats: Res<RoundStats>,
+    player_count: Res<PlayerCount>,
+    arena: Res<Arena>,
+    levels: Res<Levels>,
+    config: Res<GameConfig>,
+) {
+    let scores = round_stats.scores_at_start[..**player_count]
+        .iter()
+        .map(|score| Score {
+            last_eat_time: score.last_eat_time - round_stats.start_time,
+            ..score.clone()
+        })
+        .collect();
+    commands.insert_resource(ReplayRecorder(Replay {
+        seed: round_stats.seed,
+        players: **player_count as u8,
+        level: round_stats.level as u8,
+        level_def: levels.current().clone(),
+        scores,
+        arena_width: arena.width,
+        arena_height: arena.height,
+        config: config.clone(),
+        frames: Vec::new(),
+    }));
+}
+
+pub fn replay_record_frame(input: Res<GameInput>, recorder: Option<ResMut<ReplayRecorder>>) {
+    if let Some(mut recorder) = recorder {
+        recorder.0.frames.push(*input);
+    }
+}
+
+pub fn replay_save_recording(mut commands: Commands, recorder: Option<Res<ReplayRecorder>>) {
+    let Some(recorder) = recorder else {
+        return;
+    };
+    match storage::save_bytes(LAST_ROUND_KEY, &recorder.0.encode()) {
+        Ok(location) => info!(
+            "saved replay of {} frames to {location}",
+            recorder.0.frames.len()
+        ),
+        Err(e) => warn!("failed to save replay: {e}"),
+    }
+    commands.remove_resource::<ReplayRecorder>();
+}
+
+/// Run condition for what only happens in rounds played live
+pub fn not_playing_back(playback: Option<Res<ReplayPlayback>>) -> bool {
+    playback.is_none()
+}
+
+/// Sets the round up as it was recorded. The arena stays the recorded size
+/// whatever the window does, and the level is set when the round starts.
+pub fn replay_start(
+    playback: Res<ReplayPlayback>,
+    mut player_count: ResMut<PlayerCount>,
+    mut arena: ResMut<Arena>,
+    mut next_state: ResMut<NextState<crate::GameState>>,
+) {
+    **player_count = playback.replay.players.clamp(1, MAX_PLAYERS as u8) as usize;
+    arena.width = playback.replay.arena_width;
+    arena.height = playback.replay.arena_height;
+    next_state.set(crate::GameState::Playing);
+}
+
+/// Feeds the recorded input to the simulation, one frame per fixed update
+pub fn replay_playback(mut playback: ResMut<ReplayPlayback>, mut input: ResMut<GameInput>) {
+    let index = playback.next_frame;
+    match playback.replay.frames.get(index) {
+        Some(frame) => *input = *frame,
+        None => {
+            if index == playback.replay.frames.len() {
+                warn!("replay ran out of input, the simulation diverged from the recording");
+            }
+            *input = GameInput {
+                quit: true,
+                ..default()
+            };
+        }
+    }
+    playback.next_frame += 1;
+}
+
+pub fn replay_finished(mut exit: EventWriter<AppExit>) {
+    info!("replay finished");
+    exit.send(AppExit);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn replay(frames: Vec<GameInput>) -> Replay {
+        Replay {
+            seed: 0x1234_5678_9abc_def0,
+            players: 2,
+            level: 3,
+            level_def: Level {
+                name: "recorded".to_string(),
+                candy_spawn_seconds: 1.25,
+                initial_candies: 7,
+                target: 12,
+                ..default()
+            },
+            scores: vec![
+                Score {
+                    points: 420,
+                    candies_eaten: 17,
+                    combo: 3,
+                    best_combo: 5,
+                    last_eat_time: -0.25,
+                },
+                Score::default(),
+            ],
+            arena_width: 1280.0,
+            arena_height: 720.0,
+            config: GameConfig::default(),
+            frames,
+        }
+    }
+
+    fn frames() -> Vec<GameInput> {
+        let steer = GameInput {
+            directions: [Vec2::new(0.5, -1.0), Vec2::new(-0.25, 0.0)],
+            ..default()
+        }
+        .quantized();
+        let quit = GameInput {
+            quit: true,
+            ..default()
+        };
+        // Long enough a run to be split into several records
+        let mut frames = vec![GameInput::default(); u16::MAX as usize + 10];
+        frames.extend([steer, steer, GameInput::default(), quit]);
+        frames
+    }
+
+    #[test]
+    fn encode_decode_round_trip() {
+        let original = replay(frames());
+        let decoded = Replay::decode(&original.encode()).unwrap();
+
+        assert_eq!(decoded.seed, original.seed);
+        assert_eq!(decoded.players, original.players);
+        assert_eq!(decoded.level, original.level);
+        assert_eq!(
+            ron::to_string(&decoded.level_def).unwrap(),
+            ron::to_string(&original.level_def).unwrap()
+        );
+        assert_eq!(decoded.scores.len(), original.scores.len());
+        for (decoded, original) in decoded.scores.iter().zip(&original.scores) {
+            assert_eq!(decoded.points, original.points);
+            assert_eq!(decoded.candies_eaten, original.candies_eaten);
+            assert_eq!(decoded.combo, original.combo);
+            assert_eq!(decoded.best_combo, original.best_combo);
+            assert_eq!(decoded.last_eat_time, original.last_eat_time);
+        }
+        assert_eq!(decoded.arena_width, original.arena_width);
+        assert_eq!(decoded.arena_height, original.arena_height);
+        assert_eq!(
+            ron::to_string(&decoded.config).unwrap(),
+            ron::to_string(&original.config).unwrap()
+        );
+        assert_eq!(decoded.frames, original.frames);
+    }
+
+    #[test]
+    fn truncated_header_is_rejected() {
+        let header = replay(Vec::new()).encode();
+        for len in 0..header.len() {
+            assert!(Replay::decode(&header[..len]).is_err(), "{len} bytes");
+        }
+        assert!(Replay::decode(&header).is_ok());
+    }
+
+    #[test]
+    fn truncated_frame_is_rejected() {
+        let bytes = replay(frames()).encode();
+        for cut in 1..FRAME_SIZE {
+            assert!(Replay::decode(&bytes[..bytes.len() - cut]).is_err());
+        }
+    }
+
+    #[test]
+    fn other_versions_are_rejected() {
+        let mut bytes = replay(frames()).encode();
+        bytes[MAGIC.len()] = VERSION - 1;
+        assert!(Replay::decode(&bytes).is_err());
+        bytes[MAGIC.len()] = VERSION + 1;
+        assert!(Replay::decode(&bytes).is_err());
+    }
+
+    #[test]
+    fn other_files_are_rejected() {
+        assert!(Replay::decode(b"").is_err());
+        assert!(Replay::decode(b"not a replay at all").is_err());
+    }
+}
@@ -0,0 +1,38 @@
+use bevy::prelude::*;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+
+/// All randomness of a round comes from here, seeded per round so that any
+/// round can be reproduced by passing its seed to `--seed`.
+#[derive(Resource)]
+pub struct GameRng {
+    /// Drives the simulation (candy spawns)
+    pub gameplay: ChaCha8Rng,
+    /// Cosmetic choices like sound variants, kept apart so that playing
+    /// sounds or not never changes the simulation
+    pub effects: ChaCha8Rng,
+    seed: u64,
+    seeds: ChaCha8Rng,
+}
+
+impl GameRng {
+    /// The first round uses `seed` (random if none), later rounds use seeds derived from it
+    pub fn new(seed: Option<u64>) -> Self {
+        let seed = seed.unwrap_or_else(rand::random);
+        GameRng {
+            gameplay: ChaCha8Rng::seed_from_u64(seed),
+            effects: ChaCha8Rng::seed_from_u64(seed),
+            seed,
+            seeds: ChaCha8Rng::seed_from_u64(seed),
+        }
+    }
+
+    /// Reseeds for a new round and returns the seed it was given
+    pub fn start_round(&mut self) -> u64 {
+        let seed = self.seed;
+        self.gameplay = ChaCha8Rng::seed_from_u64(seed);
+        self.effects = ChaCha8Rng::seed_from_u64(!seed);
+        self.seed = self.seeds.gen();
+        seed
+    }
+}
@@ -0,0 +1,366 @@
+//! Volume and display settings, changed on the settings screen and kept next
+//! to the high scores so they survive restarts.
+
+use bevy::prelude::*;
+use bevy::window::{PresentMode, PrimaryWindow, WindowMode};
+use serde::{Deserialize, Serialize};
+
+use crate::controls::{Action, Actions};
+use crate::pause::PauseState;
+use crate::{storage, GameState, ARENA_HEIGHT, ARENA_WIDTH};
+
+const SETTINGS_KEY: &str = "settings.ron";
+
+/// Window sizes to pick from, the first one being the default
+const RESOLUTIONS: [(u32, u32); 4] = [
+    (ARENA_WIDTH as u32, ARENA_HEIGHT as u32),
+    (1024, 768),
+    (1280, 720),
+    (1920, 1080),
+];
+
+/// Volumes go up and down in steps of a tenth
+const VOLUME_STEPS: f32 = 10.0;
+
+#[derive(Resource, Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct Settings {
+    /// From 0 to 1
+    pub music_volume: f32,
+    /// From 0 to 1
+    pub sfx_volume: f32,
+    /// Silences everything without losing the volumes
+    pub muted: bool,
+    pub fullscreen: bool,
+    /// Size of the window when not fullscreen. In the browser the canvas
+    /// follows the page instead.
+    pub resolution: (u32, u32),
+    pub vsync: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            music_volume: 1.0,
+            sfx_volume: 1.0,
+            muted: false,
+            fullscreen: false,
+            resolution: RESOLUTIONS[0],
+            vsync: true,
+        }
+    }
+}
+
+impl Settings {
+    pub fn load() -> Self {
+        let Some(data) = storage::load(SETTINGS_KEY) else {
+            return Settings::default();
+        };
+        match ron::from_str::<Settings>(&data) {
+            Ok(settings) => settings,
+            Err(e) => {
+                warn!("ignoring broken settings file, using the default settings: {e}");
+                Settings::default()
+            }
+        }
+    }
+
+    pub fn save(&self) {
+        let data = match ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default()) {
+            Ok(data) => data,
+            Err(e) => {
+                error!("failed to serialize settings: {e}");
+                return;
+            }
+        };
+        if let Err(e) = storage::save(SETTINGS_KEY, &data) {
+            error!("failed to save settings: {e}");
+        }
+    }
+
+    /// What the music should play at, taking mute into account
+    pub fn music_volume(&self) -> f32 {
+        if self.muted {
+            0.0
+        } else {
+            self.music_volume
+        }
+    }
+
+    /// What sound effects should play at, taking mute into account
+    pub fn sfx_volume(&self) -> f32 {
+        if self.muted {
+            0.0
+        } else {
+            self.sfx_volume
+        }
+    }
+
+    pub fn window_mode(&self) -> WindowMode {
+        if self.fullscreen {
+            WindowMode::BorderlessFullscreen
+        } else {
+            WindowMode::Windowed
+        }
+    }
+
+    pub fn present_mode(&self) -> PresentMode {
+        if self.vsync {
+            PresentMode::AutoVsync
+        } else {
+            PresentMode::AutoNoVsync
+        }
+    }
+
+    fn step_resolution(&mut self, step: isize) {
+        let current = RESOLUTIONS
+            .iter()
+            .position(|&resolution| resolution == self.resolution)
+            .unwrap_or(0) as isize;
+        let next = (current + step).rem_euclid(RESOLUTIONS.len() as isize);
+        self.resolution = RESOLUTIONS[next as usize];
+    }
+}
+
+fn step_volume(volume: f32, step: isize) -> f32 {
+    ((volume * VOLUME_STEPS).round() + step as f32).clamp(0.0, VOLUME_STEPS) / VOLUME_STEPS
+}
+
+fn volume_bar(volume: f32) -> String {
+    let filled = (volume * VOLUME_STEPS).round() as usize;
+    format!(
+        "[{}{}] {:>3}%",
+        "#".repeat(filled),
+        "-".repeat(VOLUME_STEPS as usize - filled),
+        (volume * 100.0).round()
+    )
+}
+
+fn on_off(value: bool) -> &'static str {
+    if value {
+        "on"
+    } else {
+        "off"
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum SettingsOption {
+    MusicVolume,
+    SfxVolume,
+    Mute,
+    Fullscreen,
+    Resolution,
+    Vsync,
+    Back,
+}
+
+impl SettingsOption {
+    const ALL: [SettingsOption; 7] = [
+        SettingsOption::MusicVolume,
+        SettingsOption::SfxVolume,
+        SettingsOption::Mute,
+        SettingsOption::Fullscreen,
+        SettingsOption::Resolution,
+        SettingsOption::Vsync,
+        SettingsOption::Back,
+    ];
+
+    fn describe(self, settings: &Settings) -> String {
+        match self {
+            SettingsOption::MusicVolume => {
+                format!("music volume  {}", volume_bar(settings.music_volume))
+            }
+            SettingsOption::SfxVolume => {
+                format!("sound volume  {}", volume_bar(settings.sfx_volume))
+            }
+            SettingsOption::Mute => format!("mute          {}", on_off(settings.muted)),
+            SettingsOption::Fullscreen => format!(
+                "display       {}",
+                if settings.fullscreen {
+                    "fullscreen"
+                } else {
+                    "windowed"
+                }
+            ),
+            SettingsOption::Resolution => format!(
+                "resolution    {}x{}",
+                settings.resolution.0, settings.resolution.1
+            ),
+            SettingsOption::Vsync => format!("vsync         {}", on_off(settings.vsync)),
+            SettingsOption::Back => "back".to_string(),
+        }
+    }
+}
+
+/// Where the settings screen was opened from, which is where it goes back to
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SettingsReturn {
+    Title,
+    Pause,
+}
+
+/// Inserted by whoever opens the settings screen
+#[derive(Resource)]
+pub struct SettingsMenu {
+    selected: usize,
+    return_to: SettingsReturn,
+}
+
+impl SettingsMenu {
+    pub fn new(return_to: SettingsReturn) -> Self {
+        SettingsMenu {
+            selected: 0,
+            return_to,
+        }
+    }
+
+    fn selected(&self) -> SettingsOption {
+        SettingsOption::ALL[self.selected]
+    }
+}
+
+#[derive(Component)]
+pub struct SettingsOverlay {}
+
+#[derive(Component)]
+pub struct SettingsMenuText {}
+
+pub fn settings_setup(mut commands: Commands, asset_server: Res<AssetServer>) {
+    info!("settings_setup");
+
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    width: Val::Percent(100.0),
+                    height: Val::Percent(100.0),
+                    justify_content: JustifyContent::Center,
+                    align_items: AlignItems::Center,
+                    ..default()
+                },
+                background_color: Color::rgba(0.0, 0.0, 0.0, 0.6).into(),
+                ..default()
+            },
+            SettingsOverlay {},
+        ))
+        .with_children(|parent| {
+            let style = TextStyle {
+                font: asset_server.load("fonts/MesloLGS NF Regular.ttf"),
+                font_size: 30.0,
+                color: Color::WHITE,
+            };
+            parent.spawn((
+                TextBundle::from_sections(
+                    std::iter::once(TextSection::new("settings\n\n", style.clone())).chain(
+                        SettingsOption::ALL
+                            .iter()
+                            .map(|_| TextSection::new("", style.clone())),
+                    ),
+                )
+                .with_text_alignment(TextAlignment::Left),
+                SettingsMenuText {},
+            ));
+        });
+}
+
+/// Up and down pick a setting, left and right change it, start toggles it.
+/// Changes take effect right away.
+pub fn settings_input(
+    actions: Actions,
+    keyboard_input: Res<Input<KeyCode>>,
+    mut menu: ResMut<SettingsMenu>,
+    mut settings: ResMut<Settings>,
+    mut window_query: Query<&mut Window, With<PrimaryWindow>>,
+    mut text_query: Query<&mut bevy::text::Text, With<SettingsMenuText>>,
+    mut next_game_state: ResMut<NextState<GameState>>,
+    mut next_pause_state: ResMut<NextState<PauseState>>,
+) {
+    let count = SettingsOption::ALL.len() as isize;
+    if actions.just_pressed(Action::MoveUp) {
+        menu.selected = (menu.selected as isize - 1).rem_euclid(count) as usize;
+    }
+    if actions.just_pressed(Action::MoveDown) {
+        menu.selected = (menu.selected as isize + 1).rem_euclid(count) as usize;
+    }
+
+    let confirm =
+        actions.just_pressed(Action::Start) || keyboard_input.just_pressed(KeyCode::Return);
+    let step = if actions.just_pressed(Action::MoveLeft) {
+        -1
+    } else if actions.just_pressed(Action::MoveRight) || confirm {
+        1
+    } else {
+        0
+    };
+
+    let close =
+        actions.just_pressed(Action::Pause) || (confirm && menu.selected() == SettingsOption::Back);
+    if close {
+        match menu.return_to {
+            SettingsReturn::Title => next_game_state.set(GameState::Title),
+            SettingsReturn::Pause => next_pause_state.set(PauseState::Paused),
+        }
+        return;
+    }
+
+    if step != 0 {
+        match menu.selected() {
+            SettingsOption::MusicVolume => {
+                settings.music_volume = step_volume(settings.music_volume, step)
+            }
+            SettingsOption::SfxVolume => {
+                settings.sfx_volume = step_volume(settings.sfx_volume, step)
+            }
+            SettingsOption::Mute => settings.muted = !settings.muted,
+            SettingsOption::Fullscreen => settings.fullscreen = !settings.fullscreen,
+            SettingsOption::Resolution => settings.step_resolution(step),
+            SettingsOption::Vsync => settings.vsync = !settings.vsync,
+            SettingsOption::Back => {}
+        }
+
+        if let Ok(mut window) = window_query.get_single_mut() {
+            window.mode = settings.window_mode();
+            window.present_mode = settings.present_mode();
+            // Only when asked for, so that a window resized by hand keeps
+            // its size
+            if menu.selected() == SettingsOption::Resolution && !cfg!(target_arch = "wasm32") {
+                let (width, height) = settings.resolution;
+                window.resolution.set(width as f32, height as f32);
+            }
+        }
+    }
+
+    if let Ok(mut text) = text_query.get_single_mut() {
+        for (index, option) in SettingsOption::ALL.iter().enumerate() {
+            let section = &mut text.sections[index + 1];
+            let selected = index == menu.selected;
+            section.value = format!(
+                "{} {}\n",
+                if selected { ">" } else { " " },
+                option.describe(&settings)
+            );
+            section.style.color = if selected {
+                Color::YELLOW
+            } else {
+                Color::WHITE
+            };
+        }
+    }
+}
+
+pub fn settings_teardown(
+    mut commands: Commands,
+    entities: Query<Entity, With<SettingsOverlay>>,
+    settings: Res<Settings>,
+) {
+    info!("settings_teardown");
+
+    for entity in &entities {
+        commands.entity(entity).despawn_recursive();
+    }
+    commands.remove_resource::<SettingsMenu>();
+
+    settings.save();
+}
@@ -0,0 +1,92 @@
+//! Tiny key/value persistence. Native builds keep one file per key in a
+//! per-user data directory, wasm builds use the browser's `localStorage`.
+
+#[cfg(not(target_arch = "wasm32"))]
+use std::path::PathBuf;
+
+#[cfg(not(target_arch = "wasm32"))]
+fn data_dir() -> PathBuf {
+    if let Some(dir) = std::env::var_os("CATICORN_DATA_DIR") {
+        return PathBuf::from(dir);
+    }
+    if let Some(dir) = std::env::var_os("APPDATA") {
+        return PathBuf::from(dir).join("caticorn");
+    }
+    if let Some(dir) = std::env::var_os("XDG_DATA_HOME") {
+        return PathBuf::from(dir).join("caticorn");
+    }
+    if let Some(home) = std::env::var_os("HOME") {
+        return PathBuf::from(home).join(".local/share/caticorn");
+    }
+    PathBuf::from(".")
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub fn load(key: &str) -> Option<String> {
+    std::fs::read_to_string(data_dir().join(key)).ok()
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub fn save(key: &str, value: &str) -> Result<(), String> {
+    let dir = data_dir();
+    std::fs::create_dir_all(&dir).map_err(|e| format!("{}: {e}", dir.display()))?;
+    let path = dir.join(key);
+    std::fs::write(&path, value).map_err(|e| format!("{}: {e}", path.display()))
+}
+
+/// Saves binary data and returns where it ended up
+#[cfg(not(target_arch = "wasm32"))]
+pub fn save_bytes(key: &str, value: &[u8]) -> Result<String, String> {
+    let dir = data_dir();
+    std::fs::create_dir_all(&dir).map_err(|e| format!("{}: {e}", dir.display()))?;
+    let path = dir.join(key);
+    std::fs::write(&path, value).map_err(|e| format!("{}: {e}", path.display()))?;
+    Ok(path.display().to_string())
+}
+
+#[cfg(target_arch = "wasm32")]
+fn local_storage() -> Option<web_sys::Storage> {
+    web_sys::window()?.local_storage().ok()?
+}
+
+#[cfg(target_arch = "wasm32")]
+pub fn load(key: &str) -> Option<String> {
+    local_storage()?.get_item(&format!("caticorn.{key}")).ok()?
+}
+
+#[cfg(target_arch = "wasm32")]
+pub fn save(key: &str, value: &str) -> Result<(), String> {
+    local_storage()
+        .ok_or_else(|| "localStorage not available".to_string())?
+        .set_item(&format!("caticorn.{key}"), value)
+        .map_err(|e| format!("{e:?}"))
+}
+
+#[cfg(target_arch = "wasm32")]
+pub fn save_bytes(_key: &str, _value: &[u8]) -> Result<String, String> {
+    Err("binary files are not supported in the browser".to_string())
+}
+
+/// Today's date (UTC) as YYYY-MM-DD
+pub fn today() -> String {
+    #[cfg(not(target_arch = "wasm32"))]
+    let seconds = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0);
+    #[cfg(target_arch = "wasm32")]
+    let seconds = (js_sys::Date::now() / 1000.0) as i64;
+
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = seconds.div_euclid(86400) + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!("{year:04}-{month:02}-{day:02}")
+}
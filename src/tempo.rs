@@ -0,0 +1,142 @@
+//! Tempo of each music track, loaded from `assets/music/music.tempo.ron`,
+//! and the beat clock that follows the music playing. Until the file has
+//! loaded, or if it can't be, the tempos built in here are used.
+
+use std::collections::BTreeMap;
+
+use bevy::asset::{AssetLoader, LoadContext, LoadedAsset};
+use bevy::prelude::*;
+use bevy::reflect::{TypePath, TypeUuid};
+use bevy::utils::BoxedFuture;
+use serde::{Deserialize, Serialize};
+
+use crate::mixer::Channels;
+
+pub const TEMPO_ASSET: &str = "music/music.tempo.ron";
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct Tempo {
+    pub bpm: f32,
+    /// Seconds into the track of its first beat
+    #[serde(default)]
+    pub offset_seconds: f32,
+}
+
+impl Tempo {
+    /// Beats since the first one, at `position` seconds into the track. The
+    /// mixer counts that from the top again each time the track loops, so a
+    /// loop that isn't exactly a whole number of beats long doesn't drift.
+    pub fn beat(&self, position: f32) -> f32 {
+        (position - self.offset_seconds) * self.bpm / 60.0
+    }
+}
+
+/// Tempos by the asset path of their track
+#[derive(Resource, Serialize, Deserialize, TypeUuid, TypePath, Clone, Debug)]
+#[uuid = "5b8e0f3c-7a2d-4c61-9e14-b3f6d2a8c0e7"]
+pub struct TempoMap {
+    pub tracks: BTreeMap<String, Tempo>,
+}
+
+impl Default for TempoMap {
+    fn default() -> Self {
+        TempoMap {
+            tracks: BTreeMap::from([
+                (
+                    "music/music_title.ogg".to_string(),
+                    Tempo {
+                        bpm: 106.0,
+                        offset_seconds: 0.0,
+                    },
+                ),
+                (
+                    "music/music_gameplay.ogg".to_string(),
+                    Tempo {
+                        bpm: 106.0,
+                        offset_seconds: 0.0,
+                    },
+                ),
+            ]),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct TempoMapLoader;
+
+impl AssetLoader for TempoMapLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), bevy::asset::Error>> {
+        Box::pin(async move {
+            let tempo_map = ron::de::from_bytes::<TempoMap>(bytes)?;
+            load_context.set_default_asset(LoadedAsset::new(tempo_map));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["tempo.ron"]
+    }
+}
+
+#[derive(Resource)]
+pub struct TempoMapHandle(Handle<TempoMap>);
+
+/// Where the music playing is, in beats
+#[derive(Resource, Default)]
+pub struct BeatClock {
+    beat: Option<f32>,
+}
+
+impl BeatClock {
+    /// Beats since the first one of the track, or nothing if no music with
+    /// a known tempo is playing
+    pub fn beat(&self) -> Option<f32> {
+        self.beat
+    }
+}
+
+pub fn tempo_setup(mut commands: Commands, asset_server: Res<AssetServer>) {
+    info!("tempo_setup");
+
+    commands.insert_resource(TempoMapHandle(asset_server.load(TEMPO_ASSET)));
+}
+
+/// Takes the tempos over from the asset server each time they (re)load
+pub fn tempo_update(
+    mut tempo_map: ResMut<TempoMap>,
+    handle: Res<TempoMapHandle>,
+    mut events: EventReader<AssetEvent<TempoMap>>,
+    tempo_maps: Res<Assets<TempoMap>>,
+) {
+    for event in events.iter() {
+        match event {
+            AssetEvent::Created { handle: changed } | AssetEvent::Modified { handle: changed }
+                if *changed == handle.0 =>
+            {
+                if let Some(loaded) = tempo_maps.get(&handle.0) {
+                    info!("loaded {TEMPO_ASSET}");
+                    *tempo_map = loaded.clone();
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Goes by how many samples of the track the mixer has had decoded
+pub fn beat_clock_update(
+    mut beat_clock: ResMut<BeatClock>,
+    channels: Res<Channels>,
+    tempo_map: Res<TempoMap>,
+    asset_server: Res<AssetServer>,
+) {
+    beat_clock.beat = channels.music_position().and_then(|(source, position)| {
+        let path = asset_server.get_handle_path(source)?;
+        let tempo = tempo_map.tracks.get(path.path().to_str()?)?;
+        Some(tempo.beat(position))
+    });
+}
@@ -0,0 +1,92 @@
+use std::time::Duration;
+
+use bevy::prelude::*;
+
+use crate::GameState;
+
+/// The simulation advances in steps of this size, whatever the frame rate
+pub const FIXED_TIMESTEP_SECONDS: f32 = 1.0 / 60.0;
+
+/// Time as seen by the simulation, advanced once per fixed update.
+/// Use this instead of `Time` in anything that runs in `FixedUpdate`.
+#[derive(Resource, Default)]
+pub struct SimulationTime {
+    delta: Duration,
+    elapsed: Duration,
+}
+
+impl SimulationTime {
+    pub fn delta(&self) -> Duration {
+        self.delta
+    }
+
+    pub fn delta_seconds(&self) -> f32 {
+        self.delta.as_secs_f32()
+    }
+
+    pub fn elapsed_seconds(&self) -> f32 {
+        self.elapsed.as_secs_f32()
+    }
+}
+
+/// Position at the previous and the latest fixed update, so that rendering
+/// can blend between them
+#[derive(Component)]
+pub struct Interpolated {
+    previous: Vec3,
+    current: Vec3,
+}
+
+impl Interpolated {
+    pub fn new(translation: Vec3) -> Self {
+        Interpolated {
+            previous: translation,
+            current: translation,
+        }
+    }
+}
+
+pub fn advance_simulation_time(
+    mut simulation_time: ResMut<SimulationTime>,
+    fixed_time: Res<FixedTime>,
+) {
+    simulation_time.delta = fixed_time.period;
+    simulation_time.elapsed += fixed_time.period;
+}
+
+/// A state change takes effect on the next frame, while several fixed updates
+/// may still run in this one. Holding the simulation until then makes the
+/// outcome independent of how fixed updates are spread over frames.
+pub fn no_state_change_pending(next_state: Res<NextState<GameState>>) -> bool {
+    next_state.0.is_none()
+}
+
+/// Undoes the interpolation so the simulation continues from where it left off
+pub fn restore_simulated_positions(mut query: Query<(&mut Transform, &mut Interpolated)>) {
+    for (mut transform, mut interpolated) in query.iter_mut() {
+        transform.translation = interpolated.current;
+        interpolated.previous = interpolated.current;
+    }
+}
+
+pub fn store_simulated_positions(mut query: Query<(&Transform, &mut Interpolated)>) {
+    for (transform, mut interpolated) in query.iter_mut() {
+        interpolated.current = transform.translation;
+    }
+}
+
+pub fn interpolate_positions(
+    mut query: Query<(&mut Transform, &Interpolated)>,
+    fixed_time: Res<FixedTime>,
+) {
+    let alpha = (fixed_time.accumulated().as_secs_f32() / fixed_time.period.as_secs_f32()).min(1.0);
+    for (mut transform, interpolated) in query.iter_mut() {
+        transform.translation = interpolated.previous.lerp(interpolated.current, alpha);
+    }
+}
+
+pub fn snap_to_simulated_positions(mut query: Query<(&mut Transform, &Interpolated)>) {
+    for (mut transform, interpolated) in query.iter_mut() {
+        transform.translation = interpolated.current;
+    }
+}